@@ -0,0 +1,152 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// Tunnel mode of a manifest row or new client
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum TunnelMode {
+    #[default]
+    Split,
+    Full,
+}
+
+/// Tags as a JSON list, or a `;`-separated string in CSV
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Tags {
+    List(Vec<String>),
+    Text(String),
+}
+
+/// One client to create, as read from a CSV or JSON manifest
+#[derive(Debug, Deserialize)]
+pub struct ClientRow {
+    pub name: String,
+    #[serde(default)]
+    pub owner: Option<String>,
+    #[serde(default)]
+    tags: Option<Tags>,
+    #[serde(default)]
+    pub ip: Option<IpAddr>,
+    #[serde(default)]
+    pub tunnel: Option<TunnelMode>,
+    #[serde(default)]
+    pub pool: Option<String>,
+}
+
+impl ClientRow {
+    pub fn tags(&self) -> Vec<String> {
+        let tags: Vec<&str> = match &self.tags {
+            Some(Tags::List(tags)) => tags.iter().map(String::as_str).collect(),
+            Some(Tags::Text(text)) => text.split(';').collect(),
+            None => Vec::new(),
+        };
+        tags.into_iter()
+            .map(str::trim)
+            .filter(|t| !t.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Whether the row is full tunnel, using `default` if it has no tunnel mode
+    pub fn full_tunnel(&self, default: TunnelMode) -> bool {
+        self.tunnel.unwrap_or(default) == TunnelMode::Full
+    }
+}
+
+/// Read a client manifest: a JSON array if the file ends in `.json`,
+/// otherwise CSV with a `name,owner,tags,ip,tunnel,pool` header (all but
+/// `name` optional).
+///
+/// Rows that fail to parse are returned as errors so every problem can be
+/// reported at once.
+pub fn read_manifest(path: &Path) -> Result<Vec<Result<ClientRow>>> {
+    let contents =
+        std::fs::read_to_string(path).with_context(|| format!("Failed to read {:?}", path))?;
+    if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("json")) {
+        let rows: Vec<serde_json::Value> =
+            serde_json::from_str(&contents).with_context(|| format!("Invalid JSON in {:?}", path))?;
+        return Ok(rows
+            .into_iter()
+            .map(|row| serde_json::from_value(row).map_err(Into::into))
+            .collect());
+    }
+    parse_csv(&contents)
+}
+
+fn parse_csv(contents: &str) -> Result<Vec<Result<ClientRow>>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .comment(Some(b'#'))
+        .from_reader(contents.as_bytes());
+    let headers = reader.headers().context("Failed to read CSV header")?;
+    if !headers.iter().any(|h| h == "name") {
+        anyhow::bail!("CSV header must include a 'name' column");
+    }
+    Ok(reader.deserialize().map(|row| row.map_err(Into::into)).collect())
+}
+
+/// Client names from a list file: one per line, `#` starts a comment
+pub fn parse_name_list(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_csv() {
+        let rows = parse_csv(
+            "name,owner,tags,ip,tunnel\n\
+             laptop,alice,staff;remote,,\n\
+             # comment\n\
+             phone, bob ,,10.0.0.50,full\n\
+             bad,,,not-an-ip,\n",
+        )
+        .unwrap();
+        assert_eq!(rows.len(), 3);
+
+        let laptop = rows[0].as_ref().unwrap();
+        assert_eq!(laptop.owner.as_deref(), Some("alice"));
+        assert_eq!(laptop.tags(), ["staff", "remote"]);
+        assert!(laptop.ip.is_none());
+        assert!(!laptop.full_tunnel(TunnelMode::Split));
+
+        let phone = rows[1].as_ref().unwrap();
+        assert_eq!(phone.owner.as_deref(), Some("bob"));
+        assert!(phone.tags().is_empty());
+        assert_eq!(phone.ip, Some("10.0.0.50".parse().unwrap()));
+        assert!(phone.full_tunnel(TunnelMode::Split));
+
+        assert!(rows[2].is_err());
+    }
+
+    #[test]
+    fn test_csv_with_only_names() {
+        let rows = parse_csv("name\na\nb\n").unwrap();
+        assert_eq!(rows.len(), 2);
+        assert!(parse_csv("owner\nalice\n").is_err());
+    }
+
+    #[test]
+    fn test_json_rows() {
+        let row: ClientRow =
+            serde_json::from_str(r#"{"name": "ci", "tags": ["bots", "ci"], "tunnel": "split"}"#).unwrap();
+        assert_eq!(row.tags(), ["bots", "ci"]);
+        assert!(!row.full_tunnel(TunnelMode::Split));
+    }
+
+    #[test]
+    fn test_parse_name_list() {
+        assert_eq!(parse_name_list("alice\n\n# gone\nbob  # left 2024\n"), ["alice", "bob"]);
+    }
+}
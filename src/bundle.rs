@@ -0,0 +1,315 @@
+use anyhow::{Context, Result};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use clap::ValueEnum;
+use std::io::{Cursor, Write};
+use zip::write::SimpleFileOptions;
+use zip::{AesMode, CompressionMethod, ZipWriter};
+
+use crate::config::{ClientConfig, WireGuardConfig};
+use crate::export::{ExportOptions, Format, RenderedFile, Renderer};
+
+/// Name of the instructions file inside archives
+const INSTRUCTIONS_FILE: &str = "README.txt";
+
+/// Per-platform import steps; `{conf}` is replaced with the config file name
+const PLATFORMS: &[(&str, &[&str])] = &[
+    (
+        "Android and iPhone/iPad",
+        &[
+            "Install the WireGuard app from Google Play or the App Store.",
+            "Tap +, then scan the QR code, or import {conf}.",
+            "Switch the new tunnel on.",
+        ],
+    ),
+    (
+        "Windows",
+        &[
+            "Install WireGuard from https://www.wireguard.com/install/.",
+            "Click \"Import tunnel(s) from file\" and choose {conf}.",
+            "Click Activate.",
+        ],
+    ),
+    (
+        "macOS",
+        &[
+            "Install WireGuard from the Mac App Store.",
+            "Click \"Import tunnel(s) from file\" and choose {conf}.",
+            "Click Activate and allow the VPN configuration when asked.",
+        ],
+    ),
+    (
+        "Linux",
+        &[
+            "Install wireguard-tools from your distribution.",
+            "Run: sudo install -m 600 {conf} /etc/wireguard/wg0.conf",
+            "Run: sudo wg-quick up wg0",
+        ],
+    ),
+];
+
+/// Container for an onboarding bundle
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum BundleFormat {
+    /// Zip archive, optionally password-protected (AES-256)
+    #[default]
+    Zip,
+    /// Uncompressed tar archive
+    Tar,
+    /// Single HTML page with everything embedded
+    Html,
+}
+
+impl BundleFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            BundleFormat::Zip => "zip",
+            BundleFormat::Tar => "tar",
+            BundleFormat::Html => "html",
+        }
+    }
+}
+
+/// A QR code of the client config in both image formats
+pub struct QrImages {
+    pub png: Vec<u8>,
+    pub svg: String,
+}
+
+/// Everything handed to the person setting up a client
+pub struct Bundle {
+    pub name: String,
+    /// The wg-quick config, which every WireGuard app can import
+    pub config: String,
+    pub qr: Option<QrImages>,
+    /// The config in other formats, for people who need them
+    pub alternates: Vec<RenderedFile>,
+}
+
+impl Bundle {
+    /// Collect a client's config and its `alternates`, rendered from state
+    pub fn new(client: &ClientConfig, alternates: &[Format]) -> Result<Self> {
+        let mut files = Vec::new();
+        for format in alternates.iter().filter(|f| !f.is_default()) {
+            files.extend(format.render_client(client, &ExportOptions::default())?);
+        }
+        Ok(Self {
+            name: client.name.clone(),
+            config: client.to_wireguard_config()?,
+            qr: None,
+            alternates: files,
+        })
+    }
+
+    fn config_file(&self) -> String {
+        format!("{}.conf", self.name)
+    }
+
+    /// Plain-text setup instructions
+    pub fn instructions(&self) -> String {
+        let mut text = format!(
+            "WireGuard VPN setup for '{}'\n\n\
+             Keep these files private: {} contains your private key.\n",
+            self.name,
+            self.config_file()
+        );
+        for (platform, steps) in PLATFORMS {
+            text.push_str(&format!("\n{}\n", platform));
+            for (i, step) in steps.iter().enumerate() {
+                text.push_str(&format!("  {}. {}\n", i + 1, step.replace("{conf}", &self.config_file())));
+            }
+        }
+        if !self.alternates.is_empty() {
+            text.push_str("\nOther formats\n");
+            for file in &self.alternates {
+                text.push_str(&format!("  {}\n", file.name));
+            }
+        }
+        text
+    }
+
+    /// Files in an archive, all inside a `<name>/` directory
+    fn files(&self) -> Vec<(String, Vec<u8>)> {
+        let mut files = vec![
+            (INSTRUCTIONS_FILE.to_string(), self.instructions().into_bytes()),
+            (self.config_file(), self.config.clone().into_bytes()),
+        ];
+        if let Some(qr) = &self.qr {
+            files.push((format!("{}.png", self.name), qr.png.clone()));
+        }
+        for file in &self.alternates {
+            files.push((file.name.clone(), file.contents.clone().into_bytes()));
+        }
+        files
+            .into_iter()
+            .map(|(name, contents)| (format!("{}/{}", self.name, name), contents))
+            .collect()
+    }
+
+    /// Render the bundle; only zip bundles can be password-protected
+    pub fn render(&self, format: BundleFormat, password: Option<&str>) -> Result<Vec<u8>> {
+        match (format, password) {
+            (BundleFormat::Zip, _) => self.to_zip(password),
+            (_, Some(_)) => anyhow::bail!("Password protection is only available for zip bundles"),
+            (BundleFormat::Tar, None) => self.to_tar(),
+            (BundleFormat::Html, None) => Ok(self.to_html().into_bytes()),
+        }
+    }
+
+    fn to_zip(&self, password: Option<&str>) -> Result<Vec<u8>> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let mut options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .unix_permissions(0o600);
+        if let Some(password) = password {
+            options = options.with_aes_encryption(AesMode::Aes256, password);
+        }
+        for (name, contents) in self.files() {
+            zip.start_file(name, options).context("Failed to write zip")?;
+            zip.write_all(&contents).context("Failed to write zip")?;
+        }
+        Ok(zip.finish().context("Failed to write zip")?.into_inner())
+    }
+
+    /// A POSIX ustar archive of regular files, readable only by the owner
+    fn to_tar(&self) -> Result<Vec<u8>> {
+        let mut tar = tar::Builder::new(Vec::new());
+        for (name, contents) in self.files() {
+            let mut header = tar::Header::new_ustar();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o600);
+            header.set_entry_type(tar::EntryType::Regular);
+            tar.append_data(&mut header, &name, contents.as_slice())
+                .with_context(|| format!("Failed to add '{}' to the tar archive", name))?;
+        }
+        tar.into_inner().context("Failed to write tar")
+    }
+
+    /// A standalone page: instructions, QR code and download links
+    fn to_html(&self) -> String {
+        let mut html = format!(
+            "<!DOCTYPE html>\n\
+             <html lang=\"en\">\n\
+             <head>\n\
+             <meta charset=\"utf-8\">\n\
+             <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+             <title>WireGuard setup for {name}</title>\n\
+             <style>body{{font-family:sans-serif;max-width:40em;margin:2em auto;padding:0 1em}}\
+             pre{{background:#f4f4f4;padding:1em;overflow-x:auto}}svg{{max-width:100%;height:auto}}</style>\n\
+             </head>\n\
+             <body>\n\
+             <h1>WireGuard setup for {name}</h1>\n\
+             <p>Keep this page private: it contains your private key.</p>\n",
+            name = html_escape(&self.name)
+        );
+        if let Some(qr) = &self.qr {
+            // Inline SVG must not carry its own XML declaration
+            let svg = qr.svg.find("<svg").map_or(qr.svg.as_str(), |start| &qr.svg[start..]);
+            html.push_str(&format!("<h2>Scan with the WireGuard app</h2>\n{}\n", svg));
+        }
+        html.push_str(&format!(
+            "<h2>Config file</h2>\n<p>{}</p>\n",
+            download_link(&self.config_file(), &self.config)
+        ));
+        for (platform, steps) in PLATFORMS {
+            html.push_str(&format!("<h2>{}</h2>\n<ol>\n", html_escape(platform)));
+            for step in *steps {
+                let step = html_escape(step).replace("{conf}", &html_escape(&self.config_file()));
+                html.push_str(&format!("<li>{}</li>\n", step));
+            }
+            html.push_str("</ol>\n");
+        }
+        if !self.alternates.is_empty() {
+            html.push_str("<h2>Other formats</h2>\n");
+            for file in &self.alternates {
+                html.push_str(&format!(
+                    "<details>\n<summary>{}</summary>\n<pre>{}</pre>\n</details>\n",
+                    download_link(&file.name, &file.contents),
+                    html_escape(&file.contents)
+                ));
+            }
+        }
+        html.push_str(&format!(
+            "<details>\n<summary>Show config</summary>\n<pre>{}</pre>\n</details>\n\
+             </body>\n\
+             </html>\n",
+            html_escape(&self.config)
+        ));
+        html
+    }
+}
+
+/// Escape text for HTML element content and attribute values
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// A link that downloads `contents` as `name` without a server
+fn download_link(name: &str, contents: &str) -> String {
+    format!(
+        "<a download=\"{name}\" href=\"data:application/octet-stream;base64,{data}\">Download {name}</a>",
+        name = html_escape(name),
+        data = STANDARD.encode(contents)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_support;
+    use std::io::Read;
+
+    fn bundle(alternates: &[Format]) -> Bundle {
+        let client = test_support::client(&mut test_support::server(), "phone", "0.0.0.0/0");
+        Bundle::new(&client, alternates).unwrap()
+    }
+
+    #[test]
+    fn test_zip_with_password() {
+        let bundle = bundle(&[Format::Nm]);
+        let zip = bundle.render(BundleFormat::Zip, Some("hunter2")).unwrap();
+        let mut archive = zip::ZipArchive::new(Cursor::new(zip)).unwrap();
+        let names: Vec<String> = archive.file_names().map(|n| n.unwrap().into_owned()).collect();
+        assert!(names.contains(&"phone/README.txt".to_string()));
+        assert!(names.contains(&"phone/phone.nmconnection".to_string()));
+
+        assert!(archive.by_name("phone/phone.conf").is_err());
+        let mut config = String::new();
+        archive
+            .by_name_decrypt("phone/phone.conf", b"hunter2")
+            .unwrap()
+            .read_to_string(&mut config)
+            .unwrap();
+        assert_eq!(config, bundle.config);
+    }
+
+    #[test]
+    fn test_tar_layout() {
+        let bundle = bundle(&[]);
+        let tar = bundle.render(BundleFormat::Tar, None).unwrap();
+        let mut archive = tar::Archive::new(tar.as_slice());
+        let mut entries = Vec::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            assert_eq!(entry.header().mode().unwrap(), 0o600);
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents).unwrap();
+            entries.push((entry.path().unwrap().display().to_string(), contents));
+        }
+        assert_eq!(entries[0].0, "phone/README.txt");
+        assert_eq!(entries[1], ("phone/phone.conf".to_string(), bundle.config.clone()));
+
+        assert!(bundle.render(BundleFormat::Tar, Some("pw")).is_err());
+    }
+
+    #[test]
+    fn test_html_embeds_config() {
+        let html = String::from_utf8(bundle(&[]).render(BundleFormat::Html, None).unwrap()).unwrap();
+        assert!(html.contains("<title>WireGuard setup for phone</title>"));
+        assert!(html.contains("download=\"phone.conf\""));
+        assert!(html.contains("choose phone.conf"));
+        assert!(html.contains("[Interface]\nPrivateKey = "));
+    }
+}
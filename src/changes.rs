@@ -0,0 +1,526 @@
+use anyhow::{Context, Result};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use similar::TextDiff;
+use std::fmt::Write as _;
+use std::fs::{self, OpenOptions};
+use std::io::Write as _;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use crate::context::{self, STATE_FILE};
+use crate::crypto::KEY_LEN;
+
+/// Shown in diffs instead of private and preshared keys
+const REDACTED: &str = "<redacted>";
+
+/// A single pending change to a file on disk
+#[derive(Debug, Clone)]
+pub enum FileChange {
+    Write { path: PathBuf, contents: String },
+    /// Write only if the file does not exist yet, for output that differs on
+    /// every render (such as encrypted files) and is known to be current
+    Create { path: PathBuf, contents: String },
+    /// Write raw bytes, such as a QR image or an archive; diffs only say that it changes
+    Binary { path: PathBuf, contents: Vec<u8> },
+    Remove { path: PathBuf },
+}
+
+/// How a file on disk compares to what a change would make it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Drift {
+    UpToDate,
+    Modified,
+    Missing,
+    Unexpected,
+}
+
+impl FileChange {
+    pub fn path(&self) -> &Path {
+        match self {
+            FileChange::Write { path, .. }
+            | FileChange::Create { path, .. }
+            | FileChange::Binary { path, .. }
+            | FileChange::Remove { path } => path,
+        }
+    }
+
+    /// Compare the file on disk with the result of this change
+    pub fn drift(&self) -> Result<Drift> {
+        if let FileChange::Binary { path, contents } = self {
+            return Ok(match read_existing_bytes(path)? {
+                Some(current) if current == *contents => Drift::UpToDate,
+                Some(_) => Drift::Modified,
+                None => Drift::Missing,
+            });
+        }
+        let current = read_existing(self.path())?;
+        Ok(match (self, current) {
+            (FileChange::Write { contents, .. }, Some(current)) if *contents == current => {
+                Drift::UpToDate
+            }
+            (FileChange::Write { .. }, Some(_)) => Drift::Modified,
+            (FileChange::Write { .. }, None) => Drift::Missing,
+            (FileChange::Create { .. }, Some(_)) => Drift::UpToDate,
+            (FileChange::Create { .. }, None) => Drift::Missing,
+            (FileChange::Binary { .. }, _) => unreachable!("binary changes are compared above"),
+            (FileChange::Remove { .. }, Some(_)) => Drift::Unexpected,
+            (FileChange::Remove { .. }, None) => Drift::UpToDate,
+        })
+    }
+
+    /// Unified diff from the file on disk to the result of this change,
+    /// or `None` if nothing would change.
+    ///
+    /// Private and preshared keys are redacted on both sides.
+    pub fn diff(&self) -> Result<Option<String>> {
+        let path = self.path();
+        if let FileChange::Binary { contents, .. } = self {
+            let current = read_existing_bytes(path)?;
+            if current.as_ref() == Some(contents) {
+                return Ok(None);
+            }
+            let old_name = match current {
+                Some(_) => format!("a/{}", path.display()),
+                None => "/dev/null".to_string(),
+            };
+            return Ok(Some(format!(
+                "--- {}\n+++ b/{}\n(binary file, {} bytes)\n",
+                old_name,
+                path.display(),
+                contents.len()
+            )));
+        }
+        let current = read_existing(path)?;
+        let proposed = match self {
+            FileChange::Write { contents, .. } => Some(contents.as_str()),
+            FileChange::Create { .. } if current.is_some() => return Ok(None),
+            FileChange::Create { contents, .. } => Some(contents.as_str()),
+            FileChange::Binary { .. } => unreachable!("binary changes are diffed above"),
+            FileChange::Remove { .. } => None,
+        };
+        if current.as_deref() == proposed {
+            return Ok(None);
+        }
+
+        let old_name = match current {
+            Some(_) => format!("a/{}", path.display()),
+            None => "/dev/null".to_string(),
+        };
+        let new_name = match proposed {
+            Some(_) => format!("b/{}", path.display()),
+            None => "/dev/null".to_string(),
+        };
+        let old = redact(current.as_deref().unwrap_or(""));
+        let new = redact(proposed.unwrap_or(""));
+        if old == new {
+            return Ok(Some(format!(
+                "--- {}\n+++ {}\n(only redacted key material changes)\n",
+                old_name, new_name
+            )));
+        }
+        Ok(Some(
+            TextDiff::from_lines(&old, &new)
+                .unified_diff()
+                .header(&old_name, &new_name)
+                .to_string(),
+        ))
+    }
+}
+
+/// The files a command will write or remove, plus a human-readable plan.
+///
+/// Commands build a `ChangeSet` entirely in memory, then either apply it or
+/// print it as a preview when `--dry-run` is given.
+#[derive(Debug, Default)]
+pub struct ChangeSet {
+    changes: Vec<FileChange>,
+    plan: Vec<String>,
+}
+
+impl ChangeSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue `contents` to be written to `path` (mode 0600)
+    pub fn write(&mut self, path: impl Into<PathBuf>, contents: impl Into<String>) {
+        self.changes.push(FileChange::Write {
+            path: path.into(),
+            contents: contents.into(),
+        });
+    }
+
+    /// Queue `contents` to be written to `path` (mode 0600) unless it exists
+    pub fn create(&mut self, path: impl Into<PathBuf>, contents: impl Into<String>) {
+        self.changes.push(FileChange::Create {
+            path: path.into(),
+            contents: contents.into(),
+        });
+    }
+
+    /// Queue raw `contents` to be written to `path` (mode 0600)
+    pub fn write_bytes(&mut self, path: impl Into<PathBuf>, contents: Vec<u8>) {
+        self.changes.push(FileChange::Binary {
+            path: path.into(),
+            contents,
+        });
+    }
+
+    /// Queue `path` to be removed if it exists
+    pub fn remove(&mut self, path: impl Into<PathBuf>) {
+        self.changes.push(FileChange::Remove { path: path.into() });
+    }
+
+    /// Queue removal of the files `names` in `dir` that exist and no queued
+    /// change touches.
+    ///
+    /// Names that are not plain file names, and the server state, are skipped.
+    pub fn remove_unstaged(&mut self, dir: &Path, names: &[String]) {
+        let mut orphans: Vec<PathBuf> = names
+            .iter()
+            .filter(|name| context::is_safe_name(name) && *name != STATE_FILE)
+            .map(|name| dir.join(name))
+            .filter(|path| path.is_file() && !self.changes.iter().any(|change| change.path() == path))
+            .collect();
+        orphans.sort();
+        orphans.dedup();
+        for path in orphans {
+            self.remove(path);
+        }
+    }
+
+    /// Record a plan line such as `+ peer 'laptop' 10.0.0.2`
+    pub fn note(&mut self, line: impl Into<String>) {
+        self.plan.push(line.into());
+    }
+
+    pub fn changes(&self) -> &[FileChange] {
+        &self.changes
+    }
+
+    /// Write and remove files on disk.
+    ///
+    /// Every file is first written to a temporary file next to it, so a
+    /// failed write changes nothing. Then the temporary files are renamed
+    /// into place and files removed, server state last and in the order it
+    /// was staged, so the state never lists clients whose configs were not
+    /// written.
+    pub fn apply(&self) -> Result<()> {
+        let mut temps: Vec<Option<PathBuf>> = Vec::new();
+        for (i, change) in self.changes.iter().enumerate() {
+            let contents: &[u8] = match change {
+                FileChange::Write { contents, .. } => contents.as_bytes(),
+                FileChange::Binary { contents, .. } => contents,
+                FileChange::Create { path, contents } if !path.exists() => contents.as_bytes(),
+                FileChange::Create { .. } | FileChange::Remove { .. } => {
+                    temps.push(None);
+                    continue;
+                }
+            };
+            let temp = temp_path(change.path(), i);
+            if let Err(e) = write_private(&temp, contents)
+                .with_context(|| format!("Failed to write {:?}", change.path()))
+            {
+                for temp in temps.iter().flatten().chain([&temp]) {
+                    let _ = fs::remove_file(temp);
+                }
+                return Err(e);
+            }
+            temps.push(Some(temp));
+        }
+
+        let is_state = |change: &FileChange| change.path().file_name().is_some_and(|name| name == STATE_FILE);
+        for state in [false, true] {
+            for (change, temp) in self.changes.iter().zip(&temps) {
+                if is_state(change) != state {
+                    continue;
+                }
+                let path = change.path();
+                match (change, temp) {
+                    (_, Some(temp)) => fs::rename(temp, path)
+                        .with_context(|| format!("Failed to write {:?}", path))?,
+                    (FileChange::Remove { .. }, None) if path.exists() => fs::remove_file(path)
+                        .with_context(|| format!("Failed to remove {:?}", path))?,
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Render the plan and a unified diff of every file that would change
+    pub fn preview(&self) -> Result<String> {
+        let mut out = String::new();
+        if !self.plan.is_empty() {
+            writeln!(out, "Plan:")?;
+            for line in &self.plan {
+                writeln!(out, "  {}", line)?;
+            }
+        }
+
+        for change in &self.changes {
+            if let Some(diff) = change.diff()? {
+                writeln!(out)?;
+                write!(out, "{}", diff)?;
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+/// Hide key material in `text` before it is shown.
+///
+/// On lines naming a private or preshared key every base64 key is replaced;
+/// base64 blobs holding such a config (Kubernetes `data`) are replaced whole.
+fn redact(text: &str) -> String {
+    text.split_inclusive('\n')
+        .map(|line| {
+            let lower = line.to_ascii_lowercase();
+            if lower.contains("private") || lower.contains("preshared") {
+                replace_tokens(line, |token| {
+                    token.len() == 44 && STANDARD.decode(token).is_ok_and(|bytes| bytes.len() == KEY_LEN)
+                })
+            } else {
+                replace_tokens(line, |token| {
+                    token.len() > 44
+                        && STANDARD
+                            .decode(token)
+                            .is_ok_and(|bytes| String::from_utf8_lossy(&bytes).contains("PrivateKey"))
+                })
+            }
+        })
+        .collect()
+}
+
+/// Replace every base64 token in `line` for which `secret` holds.
+///
+/// Padding only ends a token, so `PrivateKey=<key>` splits at the `=`.
+fn replace_tokens(line: &str, secret: impl Fn(&str) -> bool) -> String {
+    let is_base64 = |c: char| c.is_ascii_alphanumeric() || matches!(c, '+' | '/');
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(start) = rest.find(is_base64) {
+        out.push_str(&rest[..start]);
+        let mut end = rest[start..].find(|c: char| !is_base64(c)).map_or(rest.len(), |len| start + len);
+        end += rest[end..].len() - rest[end..].trim_start_matches('=').len();
+        let token = &rest[start..end];
+        out.push_str(if secret(token) { REDACTED } else { token });
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Read a file if it exists
+fn read_existing(path: &Path) -> Result<Option<String>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    fs::read_to_string(path)
+        .map(Some)
+        .with_context(|| format!("Failed to read {:?}", path))
+}
+
+/// Temporary file next to `path` that change `index` is written to first
+fn temp_path(path: &Path, index: usize) -> PathBuf {
+    let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    path.with_file_name(format!(".{}.{}.rusty-wire.tmp", name, index))
+}
+
+/// Read a file's raw bytes if it exists
+fn read_existing_bytes(path: &Path) -> Result<Option<Vec<u8>>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    fs::read(path)
+        .map(Some)
+        .with_context(|| format!("Failed to read {:?}", path))
+}
+
+/// Write a file readable only by its owner.
+///
+/// New files are created with mode 0600; existing ones are tightened before
+/// anything is written to them.
+pub fn write_private(path: &Path, contents: impl AsRef<[u8]>) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .with_context(|| format!("Failed to write {:?}", path))?;
+
+    let mut permissions = file.metadata()?.permissions();
+    if (permissions.mode() & 0o777) != 0o600 {
+        permissions.set_mode(0o600);
+        file.set_permissions(permissions)?;
+    }
+    file.write_all(contents.as_ref())
+        .with_context(|| format!("Failed to write {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rusty-wire-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_preview_does_not_touch_disk() {
+        let dir = temp_dir("preview");
+        fs::write(dir.join("old.conf"), "a\nb\n").unwrap();
+
+        let mut changes = ChangeSet::new();
+        changes.note("+ peer 'laptop' 10.0.0.2");
+        changes.write(dir.join("old.conf"), "a\nc\n");
+        changes.write(dir.join("new.conf"), "x\n");
+        let preview = changes.preview().unwrap();
+
+        assert!(preview.contains("+ peer 'laptop' 10.0.0.2"));
+        assert!(preview.contains("-b\n+c\n"));
+        assert!(preview.contains("--- /dev/null"));
+        assert!(!dir.join("new.conf").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_apply_is_all_or_nothing() {
+        let dir = temp_dir("apply-collision");
+        let mut changes = ChangeSet::new();
+        changes.write(dir.join(STATE_FILE), "{}");
+        changes.write(dir.join("laptop.conf"), "a\n");
+        changes.write(dir.join("missing").join("phone.conf"), "b\n");
+        assert!(changes.apply().is_err());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+
+        let mut changes = ChangeSet::new();
+        changes.write(dir.join(STATE_FILE), "{}");
+        changes.write(dir.join("laptop.conf"), "a\n");
+        changes.apply().unwrap();
+        let mut names: Vec<String> =
+            fs::read_dir(&dir).unwrap().map(|e| e.unwrap().file_name().to_string_lossy().into_owned()).collect();
+        names.sort();
+        assert_eq!(names, ["laptop.conf", STATE_FILE]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_binary_changes() {
+        let dir = temp_dir("binary");
+        let path = dir.join("laptop.png");
+        let mut changes = ChangeSet::new();
+        changes.write_bytes(&path, vec![0x89, b'P', 0xff]);
+
+        assert_eq!(changes.changes()[0].drift().unwrap(), Drift::Missing);
+        assert!(changes.preview().unwrap().contains("+++ b/"));
+        assert!(changes.preview().unwrap().contains("(binary file, 3 bytes)"));
+        changes.apply().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), [0x89, b'P', 0xff]);
+        assert_eq!(changes.changes()[0].drift().unwrap(), Drift::UpToDate);
+        assert!(changes.preview().unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_preview_redacts_keys() {
+        let dir = temp_dir("redact");
+        let key = STANDARD.encode([7u8; 32]);
+        let public = STANDARD.encode([8u8; 32]);
+        let config = format!("PrivateKey = {}\nPublicKey = {}\n", key, public);
+
+        let mut changes = ChangeSet::new();
+        changes.write(dir.join("wg0.conf"), config.as_str());
+        changes.write(dir.join("25-wg0.netdev"), format!("[WireGuard]\nPrivateKey={}\n", key));
+        changes.write(dir.join("state.json"), format!("{{\n  \"private\": \"{}\"\n}}\n", key));
+        changes.write(dir.join("secret.yaml"), format!("data:\n  wg0.conf: {}\n", STANDARD.encode(&config)));
+        let preview = changes.preview().unwrap();
+
+        assert!(preview.contains("+PrivateKey = <redacted>\n"));
+        assert!(preview.contains("+PrivateKey=<redacted>\n"));
+        assert!(preview.contains(&format!("+PublicKey = {}\n", public)));
+        assert!(preview.contains("\"private\": \"<redacted>\""));
+        assert!(preview.contains("+  wg0.conf: <redacted>\n"));
+        assert!(!preview.contains(&key));
+
+        // A change of only the key still shows up, without the key
+        fs::write(dir.join("wg0.conf"), &config).unwrap();
+        let mut changes = ChangeSet::new();
+        changes.write(dir.join("wg0.conf"), config.replace(&key, &STANDARD.encode([9u8; 32])));
+        assert!(changes.preview().unwrap().contains("only redacted key material changes"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_drift_detection() {
+        let dir = temp_dir("drift");
+        fs::write(dir.join("same.conf"), "a\n").unwrap();
+        fs::write(dir.join("edited.conf"), "b\n").unwrap();
+
+        let mut changes = ChangeSet::new();
+        changes.write(dir.join("same.conf"), "a\n");
+        changes.write(dir.join("edited.conf"), "a\n");
+        changes.write(dir.join("missing.conf"), "a\n");
+        let drift: Vec<Drift> = changes.changes().iter().map(|c| c.drift().unwrap()).collect();
+
+        assert_eq!(drift, [Drift::UpToDate, Drift::Modified, Drift::Missing]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_remove_unstaged() {
+        let dir = temp_dir("orphans");
+        for name in ["wg0.conf", "laptop.conf", "old.conf", "old.conf.age", "wg9.conf", STATE_FILE] {
+            fs::write(dir.join(name), "x\n").unwrap();
+        }
+
+        let mut changes = ChangeSet::new();
+        changes.write(dir.join("wg0.conf"), "x\n");
+        changes.write(dir.join("laptop.conf"), "x\n");
+        let names = ["laptop.conf", "old.conf.age", "old.conf", "gone.conf", "../wg9.conf", STATE_FILE];
+        changes.remove_unstaged(&dir, &names.map(str::to_string));
+        let paths: Vec<&Path> = changes.changes()[2..].iter().map(|c| c.path()).collect();
+        assert_eq!(paths, [dir.join("old.conf"), dir.join("old.conf.age")]);
+        assert_eq!(changes.changes()[2].drift().unwrap(), Drift::Unexpected);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_create_keeps_existing_files() {
+        let dir = temp_dir("create");
+        fs::write(dir.join("old.age"), "first\n").unwrap();
+
+        let mut changes = ChangeSet::new();
+        changes.create(dir.join("old.age"), "second\n");
+        changes.create(dir.join("new.age"), "second\n");
+        assert_eq!(changes.changes()[0].drift().unwrap(), Drift::UpToDate);
+        assert!(changes.changes()[0].diff().unwrap().is_none());
+        assert_eq!(changes.changes()[1].drift().unwrap(), Drift::Missing);
+
+        changes.apply().unwrap();
+        assert_eq!(fs::read_to_string(dir.join("old.age")).unwrap(), "first\n");
+        assert_eq!(fs::read_to_string(dir.join("new.age")).unwrap(), "second\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_apply_writes_private_files_and_removes() {
+        let dir = temp_dir("apply");
+        fs::write(dir.join("gone.conf"), "x\n").unwrap();
+
+        let mut changes = ChangeSet::new();
+        changes.write(dir.join("wg0.conf"), "[Interface]\n");
+        changes.remove(dir.join("gone.conf"));
+        changes.apply().unwrap();
+
+        let mode = fs::metadata(dir.join("wg0.conf")).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(!dir.join("gone.conf").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
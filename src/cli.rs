@@ -1,5 +1,13 @@
-use clap::{Parser, Subcommand};
-use std::net::IpAddr;
+use anyhow::Result;
+use clap::{Args, Parser, Subcommand};
+
+use crate::bundle::BundleFormat;
+use crate::config::Firewall;
+use crate::export::Format;
+#[cfg(feature = "qr")]
+use crate::qr::EcLevel;
+use crate::settings::Settings;
+use std::net::{IpAddr, Ipv4Addr};
 use std::path::PathBuf;
 
 #[derive(Parser)]
@@ -10,13 +18,37 @@ pub struct Cli {
     #[command(subcommand)]
     pub command: Commands,
     
-    /// Output directory for configurations
-    #[arg(short, long, default_value = ".")]
-    pub output: PathBuf,
+    /// Output directory for configurations [default: .]
+    #[arg(short, long, global = true)]
+    pub output: Option<PathBuf>,
     
     /// Verbose output
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     pub verbose: bool,
+
+    /// Read defaults from this file instead of rusty-wire.toml
+    #[arg(long, global = true, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Server context to work in [default: the one chosen with `context use`]
+    #[arg(long, global = true, value_name = "NAME")]
+    pub server: Option<String>,
+
+    /// Show a plan and diff of the files that would change without writing them
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+}
+
+impl Cli {
+    /// Defaults from files and the environment, under the flags given here
+    pub fn settings(&self) -> Result<Settings> {
+        let settings = Settings::load(self.config.as_deref(), self.output.as_deref())?;
+        Ok(settings.merge(Settings {
+            output: self.output.clone(),
+            server: self.server.clone(),
+            ..Default::default()
+        }))
+    }
 }
 
 #[derive(Subcommand)]
@@ -31,43 +63,413 @@ pub enum Commands {
         #[arg(short, long, default_value = "51820")]
         port: u16,
         
-        /// Network subnet (e.g., 10.0.0.0/24)
+        /// Network subnet (e.g., 10.0.0.0/24); host bits are cleared
         #[arg(short, long, default_value = "10.0.0.0/24")]
         network: String,
+
+        /// Server's tunnel address [default: the network's first host]
+        #[arg(long, value_name = "IP")]
+        server_ip: Option<Ipv4Addr>,
         
         /// Network interface for NAT (e.g., eth0)
         #[arg(short, long, default_value = "eth0")]
         interface: String,
+
+        /// Format of the server config (wg-quick or networkd) [default: wg-quick]
+        #[arg(long, value_enum)]
+        format: Option<Format>,
+
+        /// Firewall for the NAT rules in wg0.conf [default: iptables]
+        #[arg(long, value_enum)]
+        firewall: Option<Firewall>,
+
+        /// WireGuard interface name [default: the first wgN unused by other contexts]
+        #[arg(long, value_name = "NAME")]
+        wg_interface: Option<String>,
+
+        /// Render with templates/<NAME>.server.j2 instead of a built-in format
+        #[arg(long, conflicts_with = "format")]
+        template: Option<String>,
     },
     
-    /// Add a new client configuration
+    /// Add a new client configuration, or manage an existing one
+    #[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
     Client {
+        #[command(subcommand)]
+        command: Option<Box<ClientCommands>>,
+
         /// Client name
-        name: String,
+        #[arg(required = true)]
+        name: Option<String>,
         
         /// Custom client IP (auto-assigned if not specified)
         #[arg(short, long)]
         ip: Option<IpAddr>,
+
+        /// Take the IP from this address pool
+        #[arg(long, conflicts_with = "ip")]
+        pool: Option<String>,
         
         /// Generate QR code for mobile clients
         #[cfg(feature = "qr")]
         #[arg(short, long)]
         qr: bool,
+
+        /// Also write the QR code to a .png or .svg image
+        #[cfg(feature = "qr")]
+        #[arg(long, value_name = "FILE")]
+        qr_out: Option<PathBuf>,
         
         /// Allow all traffic through VPN (0.0.0.0/0)
         #[arg(short, long)]
         full_tunnel: bool,
+
+        /// Only route the VPN network, overriding a full-tunnel default
+        #[arg(long, conflicts_with = "full_tunnel")]
+        split_tunnel: bool,
+
+        /// DNS servers pushed to the client (comma-separated)
+        #[arg(long, value_delimiter = ',')]
+        dns: Vec<String>,
+
+        /// PersistentKeepalive interval in seconds [default: 25]
+        #[arg(long, value_name = "SECONDS")]
+        keepalive: Option<u16>,
+
+        /// Search for a public key starting with this prefix
+        #[arg(long)]
+        vanity: Option<String>,
+
+        /// Person or team responsible for this client
+        #[arg(long)]
+        owner: Option<String>,
+
+        /// Tag for grouping clients (repeatable)
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+
+        /// Format of the client config file [default: wg-quick]
+        #[arg(long, value_enum)]
+        format: Option<Format>,
+
+        /// Render with templates/<NAME>.client.j2 instead of a built-in format
+        #[arg(long, conflicts_with = "format")]
+        template: Option<String>,
+
+        /// Write the config encrypted to this age recipient instead of in plaintext (repeatable)
+        #[cfg(feature = "age")]
+        #[arg(long, value_name = "RECIPIENT")]
+        encrypt_to: Vec<String>,
     },
     
     /// List all clients
-    List,
+    List {
+        /// List the clients of every server context
+        #[arg(long)]
+        all: bool,
+    },
     
-    /// Revoke a client
+    /// Revoke clients by name, tag or list file, all or nothing
     Revoke {
         /// Client name to revoke
-        name: String,
+        #[arg(required_unless_present_any = ["tags", "from"])]
+        name: Option<String>,
+
+        /// Revoke every client with this tag (repeatable)
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+
+        /// Revoke the clients named in this file, one per line
+        #[arg(long, value_name = "FILE")]
+        from: Option<PathBuf>,
     },
     
     /// Show server configuration
     Show,
+
+    /// Check rendered configs on disk for drift from the server state
+    Check,
+
+    /// Report overlapping or shadowed AllowedIPs in the server state
+    Lint,
+
+    /// Regenerate wg0.conf and all client configs from the server state
+    Render {
+        /// Replace a wg0.conf that has no managed block markers, dropping hand-written lines
+        #[arg(long)]
+        adopt: bool,
+    },
+
+    /// Reconcile the server and clients with a desired-state YAML file
+    Sync {
+        /// Desired state: server settings and the full list of clients
+        file: PathBuf,
+    },
+
+    /// Write an existing client's (or the server's) config in another format
+    Export {
+        /// Client name (omit to export the server config)
+        name: Option<String>,
+
+        #[command(flatten)]
+        render: RenderArgs,
+
+        #[command(flatten)]
+        options: ExportArgs,
+
+        /// Print to stdout instead of writing a file
+        #[arg(long)]
+        stdout: bool,
+
+        /// Also write a client's wg-quick config as a .png or .svg QR code
+        #[cfg(feature = "qr")]
+        #[arg(long, value_name = "FILE", requires = "name", conflicts_with = "stdout")]
+        qr_out: Option<PathBuf>,
+    },
+
+    /// Write a client's config as a QR code image for mobile import
+    #[cfg(feature = "qr")]
+    Qr {
+        /// Client name
+        name: String,
+
+        /// Image to write, .png or .svg (defaults to <NAME>.png in the output directory)
+        file: Option<PathBuf>,
+
+        /// Minimum image width and height in pixels, at most 4096
+        #[arg(
+            long,
+            default_value_t = crate::qr::DEFAULT_SIZE,
+            value_parser = clap::value_parser!(u32).range(..=i64::from(crate::qr::MAX_SIZE))
+        )]
+        size: u32,
+
+        /// Error correction level; lower levels fit larger configs
+        #[arg(long, value_enum, default_value_t)]
+        ec_level: EcLevel,
+    },
+
+    /// Build a single onboarding file for a client: config, QR code and instructions
+    Bundle {
+        /// Client name
+        name: String,
+
+        /// File to write (defaults to <NAME>-bundle.<EXT> in the output directory)
+        file: Option<PathBuf>,
+
+        /// Bundle type
+        #[arg(long, value_enum, default_value_t)]
+        format: BundleFormat,
+
+        /// Also include the config in this format (repeatable)
+        #[arg(long, value_enum)]
+        include: Vec<Format>,
+
+        /// Encrypt the zip with the password on the first line of this file ('-' for stdin)
+        #[arg(long, value_name = "FILE")]
+        password_file: Option<PathBuf>,
+    },
+
+    /// Change server-wide settings of an existing setup
+    Server {
+        #[command(subcommand)]
+        command: ServerCommands,
+    },
+
+    /// Manage address pools and ranges reserved for hand-picked IPs
+    Pool {
+        #[command(subcommand)]
+        command: PoolCommands,
+    },
+
+    /// Switch between or list the server contexts in the output directory
+    Context {
+        #[command(subcommand)]
+        command: ContextCommands,
+    },
+
+    /// Label peers in `wg show` output (read from stdin) with client names
+    Annotate,
+
+    /// Generate and inspect keys (compatible with `wg`)
+    Key {
+        #[command(subcommand)]
+        command: KeyCommands,
+    },
+}
+
+/// Format-specific export options
+#[derive(Args)]
+pub struct ExportArgs {
+    /// Path the netdev reads its private key from (networkd, default /etc/systemd/network/<ifname>.key)
+    #[arg(long)]
+    pub private_key_file: Option<PathBuf>,
+
+    /// Kubernetes Secret name (k8s)
+    #[arg(long)]
+    pub secret_name: Option<String>,
+
+    /// Kubernetes namespace (k8s)
+    #[arg(long)]
+    pub namespace: Option<String>,
+
+    /// Kubernetes label as key=value, repeatable (k8s)
+    #[arg(long = "label")]
+    pub labels: Vec<String>,
+
+    /// Encrypt the output to this age recipient (repeatable)
+    #[cfg(feature = "age")]
+    #[arg(long, value_name = "RECIPIENT")]
+    pub encrypt_to: Vec<String>,
+}
+
+/// How to re-render a stored client or server
+#[derive(Args)]
+pub struct RenderArgs {
+    /// Output format (defaults to the client's or server's own format)
+    #[arg(long, value_enum)]
+    pub format: Option<Format>,
+
+    /// Render with a user template from the templates directory
+    #[arg(long, conflicts_with = "format")]
+    pub template: Option<String>,
+
+    /// Render a client as a full tunnel (0.0.0.0/0) regardless of the stored mode
+    #[arg(long, conflicts_with = "split_tunnel")]
+    pub full_tunnel: bool,
+
+    /// Render a client as a split tunnel (VPN network only) regardless of the stored mode
+    #[arg(long)]
+    pub split_tunnel: bool,
+}
+
+#[derive(Subcommand)]
+pub enum ClientCommands {
+    /// Add every client in a CSV or JSON manifest, or none if any row fails
+    ImportBatch {
+        /// Manifest with name, owner, tags, ip and tunnel (split/full) columns
+        file: PathBuf,
+    },
+
+    /// Print a stored client's config to stdout
+    Show {
+        /// Client name
+        name: String,
+
+        #[command(flatten)]
+        render: RenderArgs,
+
+        /// Show the wg-quick config as a QR code instead
+        #[cfg(feature = "qr")]
+        #[arg(short, long)]
+        qr: bool,
+    },
+
+    /// Rename a client, keeping its keys and IP
+    Rename {
+        /// Current client name
+        name: String,
+
+        /// New client name
+        new_name: String,
+    },
+
+    /// Move a client with its keys to another server context
+    Move {
+        /// Client name
+        name: String,
+
+        /// Context to move the client to; it gets an IP there
+        #[arg(long, value_name = "CONTEXT")]
+        to: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum KeyCommands {
+    /// Generate a private key and write it to stdout
+    Genkey,
+
+    /// Read a private key from stdin and write its public key to stdout
+    Pubkey,
+
+    /// Generate a preshared key and write it to stdout
+    Genpsk,
+
+    /// Read a private key from stdin and check it matches a public key
+    Verify {
+        /// Expected public key
+        public_key: String,
+    },
+
+    /// Search for a keypair whose public key starts with a prefix
+    Vanity {
+        /// Base64 prefix the public key should start with
+        prefix: String,
+    },
+
+    /// Print a short identifier for a public key (read from stdin if omitted)
+    Fingerprint {
+        /// Public key to fingerprint
+        public_key: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ContextCommands {
+    /// List initialized contexts; the current one is marked with '*'
+    #[command(alias = "ls")]
+    List,
+
+    /// Make a context the default for later commands
+    Use {
+        /// Context name ('default' for the output directory itself)
+        name: String,
+    },
+
+    /// Print the name of the current context
+    Current,
+}
+
+#[derive(Subcommand)]
+pub enum ServerCommands {
+    /// Move the VPN to a new network and re-render every config
+    Renumber {
+        /// New network (e.g., 10.77.0.0/24); clients keep their host offsets where they fit
+        network: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum PoolCommands {
+    /// List pools and reserved ranges with the clients in each
+    #[command(alias = "ls")]
+    List,
+
+    /// Add a named block of client addresses
+    Add {
+        /// Pool name, used with `client --pool`
+        name: String,
+
+        /// Block inside the server network (e.g., 10.0.0.128/26)
+        network: String,
+    },
+
+    /// Remove a pool; its clients keep their IPs
+    Remove {
+        /// Pool name
+        name: String,
+    },
+
+    /// Keep a range out of automatic assignment
+    Reserve {
+        /// CIDR block, FIRST-LAST range or single IP
+        range: String,
+    },
+
+    /// Return a reserved range to automatic assignment
+    Unreserve {
+        /// Range as given to `reserve`
+        range: String,
+    },
 }
@@ -1,9 +1,43 @@
 use anyhow::{Context, Result};
+use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::net::{IpAddr, Ipv4Addr};
 use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::crypto::KeyPair;
+use crate::context;
+use crate::crypto::{KeyPair, PublicKey};
+use crate::export::{self, Format, INTERFACE};
+use crate::managed::{self, Block};
+use crate::pool::{self, AddressRange, Pool};
+
+/// DNS servers pushed to clients
+pub const CLIENT_DNS: [&str; 2] = ["1.1.1.1", "9.9.9.9"];
+
+/// Longest prefix with room for the server and one client
+const MAX_PREFIX_LEN: u32 = 30;
+
+/// PersistentKeepalive interval in seconds unless a client sets its own
+pub const DEFAULT_KEEPALIVE: u16 = 25;
+
+/// `rusty-wire client` subcommands, which would be parsed instead of a client name
+const RESERVED_NAMES: [&str; 5] = ["import-batch", "show", "rename", "move", "help"];
+
+/// Firewall used by the wg-quick PostUp/PostDown NAT rules
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Firewall {
+    #[default]
+    Iptables,
+    Nftables,
+}
+
+impl Firewall {
+    pub fn is_default(&self) -> bool {
+        *self == Firewall::default()
+    }
+}
 
 pub trait WireGuardConfig {
     fn to_wireguard_config(&self) -> Result<String>;
@@ -18,6 +52,39 @@ pub struct ServerConfig {
     pub keys: KeyPair,
     pub clients: Vec<ClientConfig>,
     next_ip: u32,
+    /// Format of the rendered server config
+    #[serde(default, skip_serializing_if = "Format::is_default")]
+    pub format: Format,
+    /// User template used instead of `format`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    /// Firewall the NAT rules in `wg0.conf` are written for
+    #[serde(default, skip_serializing_if = "Firewall::is_default")]
+    pub firewall: Firewall,
+    /// Name of the server's WireGuard interface, e.g. `wg1`
+    #[serde(default = "default_wg_interface", skip_serializing_if = "is_default_wg_interface")]
+    pub wg_interface: String,
+    /// Named address blocks chosen with `client --pool`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pools: Vec<Pool>,
+    /// Ranges left out of automatic assignment, for hand-picked IPs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reserved: Vec<String>,
+    /// Server's tunnel address, instead of the network's first host
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_ip: Option<Ipv4Addr>,
+    /// Files last rendered from this state, relative to the output directory,
+    /// so `render` knows which stale files are its own
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<String>,
+}
+
+fn default_wg_interface() -> String {
+    INTERFACE.to_string()
+}
+
+fn is_default_wg_interface(name: &str) -> bool {
+    name == INTERFACE
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -27,8 +94,30 @@ pub struct ClientConfig {
     pub keys: KeyPair,
     pub server_endpoint: String,
     pub server_port: u16,
-    pub server_public_key: String,
+    pub server_public_key: PublicKey,
     pub allowed_ips: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Creation date (YYYY-MM-DD, UTC)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<String>,
+    /// Format of the config file delivered to this client
+    #[serde(default, skip_serializing_if = "Format::is_default")]
+    pub format: Format,
+    /// User template used instead of `format`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    /// age recipients the delivered config is encrypted to, instead of plaintext
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub encrypt_to: Vec<String>,
+    /// DNS servers instead of `CLIENT_DNS`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns: Option<Vec<String>>,
+    /// PersistentKeepalive instead of `DEFAULT_KEEPALIVE`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keepalive: Option<u16>,
 }
 
 impl ServerConfig {
@@ -39,7 +128,9 @@ impl ServerConfig {
         interface: String,
         keys: KeyPair,
     ) -> Result<Self> {
-        // Parse network to get base IP for client assignment
+        validate_interface(&interface)?;
+        // Clear host bits so 10.0.0.5/24 means 10.0.0.0/24
+        let network = normalize_network(&network)?;
         let base_ip = Self::parse_network_base(&network)?;
         
         Ok(Self {
@@ -49,17 +140,62 @@ impl ServerConfig {
             interface,
             keys,
             clients: Vec::new(),
-            next_ip: u32::from(base_ip) + 2, // Start from .2 (server is typically .1)
+            next_ip: u32::from(base_ip) + 1, // The server's own address is skipped
+            format: Format::default(),
+            template: None,
+            firewall: Firewall::default(),
+            wg_interface: default_wg_interface(),
+            pools: Vec::new(),
+            reserved: Vec::new(),
+            server_ip: None,
+            files: Vec::new(),
         })
     }
     
     pub fn add_client(&mut self, client: &ClientConfig) -> Result<()> {
-        if self.clients.iter().any(|c| c.name == client.name) {
-            anyhow::bail!("Client '{}' already exists", client.name);
+        validate_name(&client.name)?;
+        client.validate()?;
+        self.check_new_name(&client.name, None)?;
+        if let Some(other) = self.clients.iter().find(|c| c.ip == client.ip) {
+            anyhow::bail!("IP {} is already assigned to '{}'", client.ip, other.name);
         }
+        self.check_client_ip(client.ip)?;
         self.clients.push(client.clone());
         Ok(())
     }
+
+    /// Check that no client other than `renamed` is named `name` or shares
+    /// its container name
+    fn check_new_name(&self, name: &str, renamed: Option<&str>) -> Result<()> {
+        let others = || self.clients.iter().filter(|c| Some(c.name.as_str()) != renamed);
+        if others().any(|c| c.name == name) {
+            anyhow::bail!("Client '{}' already exists", name);
+        }
+        let container_name = export::client_container_name(name);
+        if let Some(other) = others().find(|c| export::client_container_name(&c.name) == container_name) {
+            anyhow::bail!(
+                "Client '{}' would share the container name '{}' with '{}'",
+                name,
+                container_name,
+                other.name
+            );
+        }
+        Ok(())
+    }
+
+    /// Check that `ip` is a host address in the network other than the server's
+    pub fn check_client_ip(&self, ip: IpAddr) -> Result<()> {
+        if let IpAddr::V4(ip) = ip {
+            let network = AddressRange::parse(&self.network)?;
+            if ip == self.server_ip()? {
+                anyhow::bail!("IP {} is the server's address", ip);
+            }
+            if !network.contains(ip) || ip == network.start || ip == network.end {
+                anyhow::bail!("IP {} is not a host address in {}", ip, self.network);
+            }
+        }
+        Ok(())
+    }
     
     pub fn remove_client(&mut self, name: &str) -> Result<bool> {
         let initial_len = self.clients.len();
@@ -67,10 +203,185 @@ impl ServerConfig {
         Ok(self.clients.len() < initial_len)
     }
     
-    pub fn next_client_ip(&mut self) -> IpAddr {
-        let ip = Ipv4Addr::from(self.next_ip);
-        self.next_ip += 1;
-        IpAddr::V4(ip)
+    /// Rename a client, keeping its keys and address
+    pub fn rename_client(&mut self, name: &str, new_name: &str) -> Result<()> {
+        validate_name(new_name)?;
+        self.check_new_name(new_name, Some(name))?;
+        match self.clients.iter_mut().find(|c| c.name == name) {
+            Some(client) => client.name = new_name.to_string(),
+            None => anyhow::bail!("Client '{}' not found", name),
+        }
+        Ok(())
+    }
+
+    /// Add a client moved from the server on `old_network`, keeping its
+    /// keys but giving it a new IP and pointing it at this server
+    pub fn adopt_client(&mut self, client: &ClientConfig, old_network: &str) -> Result<ClientConfig> {
+        self.check_new_name(&client.name, None)?;
+        let mut client = client.clone();
+        client.ip = self.next_client_ip()?;
+        client.server_endpoint = self.endpoint.clone();
+        client.server_port = self.port;
+        client.server_public_key = self.keys.public;
+        client.replace_route(old_network, &self.network);
+        self.add_client(&client)?;
+        Ok(client)
+    }
+
+    pub fn client_by_public_key(&self, key: &str) -> Option<&ClientConfig> {
+        self.clients.iter().find(|c| c.keys.public.to_string() == key)
+    }
+
+    /// A new client with the next free IP, from `pool` if given, unless an
+    /// IP is given.
+    ///
+    /// The client is not added to the server yet.
+    pub fn new_client(
+        &mut self,
+        name: &str,
+        custom_ip: Option<IpAddr>,
+        pool: Option<&str>,
+        full_tunnel: bool,
+        keys: KeyPair,
+    ) -> Result<ClientConfig> {
+        let client_ip = match (custom_ip, pool) {
+            (Some(_), Some(_)) => anyhow::bail!("Give either an IP or a pool for '{}', not both", name),
+            (Some(ip), None) => ip,
+            (None, Some(pool)) => self.next_pool_ip(pool)?,
+            (None, None) => self.next_client_ip()?,
+        };
+        let allowed_ips = if full_tunnel {
+            "0.0.0.0/0".to_string()
+        } else {
+            self.network.clone()
+        };
+
+        Ok(ClientConfig::new(
+            name.to_string(),
+            client_ip,
+            keys,
+            self.endpoint.clone(),
+            self.port,
+            self.keys.public,
+            allowed_ips,
+        ))
+    }
+
+    /// The next unassigned client IP outside pools and reserved ranges,
+    /// skipping any chosen by hand
+    pub fn next_client_ip(&mut self) -> Result<IpAddr> {
+        let network = AddressRange::parse(&self.network)?;
+        let pools = self.pools.iter().map(Pool::range).collect::<Result<Vec<_>>>()?;
+        let taken = self.taken_ips()?;
+        let mut cursor = self.next_ip;
+        let ip = pool::allocate(&mut cursor, network, |ip| {
+            taken(ip) || pools.iter().any(|pool| pool.contains(ip))
+        })
+        .with_context(|| format!("Network {} has no free addresses", self.network))?;
+        self.next_ip = cursor;
+        Ok(IpAddr::V4(ip))
+    }
+
+    /// The next unassigned client IP in pool `name`
+    fn next_pool_ip(&mut self, name: &str) -> Result<IpAddr> {
+        let index = match self.pools.iter().position(|p| p.name == name) {
+            Some(index) => index,
+            None => anyhow::bail!("Pool '{}' not found", name),
+        };
+        let taken = self.taken_ips()?;
+        let mut pool = self.pools[index].clone();
+        let ip = pool.allocate(taken)?;
+        self.pools[index] = pool;
+        Ok(IpAddr::V4(ip))
+    }
+
+    /// Addresses automatic assignment must skip: the network's first and
+    /// last address, the server's, reserved ranges and clients' IPs
+    fn taken_ips(&self) -> Result<impl Fn(Ipv4Addr) -> bool + use<>> {
+        let network = AddressRange::parse(&self.network)?;
+        let server_ip = self.server_ip()?;
+        let reserved = self.reserved_ranges()?;
+        let used: Vec<IpAddr> = self.clients.iter().map(|c| c.ip).collect();
+        Ok(move |ip: Ipv4Addr| {
+            ip == network.start
+                || ip == network.end
+                || ip == server_ip
+                || reserved.iter().any(|range| range.contains(ip))
+                || used.contains(&IpAddr::V4(ip))
+        })
+    }
+
+    fn reserved_ranges(&self) -> Result<Vec<AddressRange>> {
+        self.reserved.iter().map(|range| AddressRange::parse(range)).collect()
+    }
+
+    /// Add a named pool; it must lie inside the network and not overlap another pool
+    pub fn add_pool(&mut self, name: &str, network: &str) -> Result<()> {
+        if name.trim().is_empty() {
+            anyhow::bail!("Pool name must not be empty");
+        }
+        if !context::is_safe_name(name) {
+            anyhow::bail!(
+                "Invalid pool name '{}': use letters, digits, '-', '_' and '.', not starting with '.'",
+                name.escape_default()
+            );
+        }
+        if self.pools.iter().any(|p| p.name == name) {
+            anyhow::bail!("Pool '{}' already exists", name);
+        }
+        let pool = Pool::new(name, network)?;
+        let range = pool.range()?;
+        if !AddressRange::parse(&self.network)?.contains_range(&range) {
+            anyhow::bail!("Pool {} is outside the server network {}", network, self.network);
+        }
+        for other in &self.pools {
+            if other.range()?.overlaps(&range) {
+                anyhow::bail!("Pool {} overlaps pool '{}' ({})", network, other.name, other.network);
+            }
+        }
+        self.pools.push(pool);
+        Ok(())
+    }
+
+    /// Pairs of a pool and a reserved range inside the network that overlap
+    pub fn reserved_in_pools(&self) -> Result<Vec<(&Pool, &str)>> {
+        let mut overlaps = Vec::new();
+        for pool in &self.pools {
+            let range = pool.range()?;
+            for reserved in &self.reserved {
+                if AddressRange::parse(reserved)?.overlaps(&range) {
+                    overlaps.push((pool, reserved.as_str()));
+                }
+            }
+        }
+        Ok(overlaps)
+    }
+
+    pub fn remove_pool(&mut self, name: &str) -> bool {
+        let initial_len = self.pools.len();
+        self.pools.retain(|p| p.name != name);
+        self.pools.len() < initial_len
+    }
+
+    /// Keep a range (CIDR, `a-b` or one IP) out of automatic assignment
+    pub fn reserve(&mut self, range: &str) -> Result<()> {
+        let parsed = AddressRange::parse(range)?;
+        if !AddressRange::parse(&self.network)?.contains_range(&parsed) {
+            anyhow::bail!("Range {} is outside the server network {}", range, self.network);
+        }
+        if self.reserved_ranges()?.contains(&parsed) {
+            anyhow::bail!("Range {} is already reserved", range);
+        }
+        self.reserved.push(range.trim().to_string());
+        Ok(())
+    }
+
+    pub fn unreserve(&mut self, range: &str) -> Result<bool> {
+        let parsed = AddressRange::parse(range)?;
+        let initial_len = self.reserved.len();
+        self.reserved
+            .retain(|r| AddressRange::parse(r).map_or(true, |r| r != parsed));
+        Ok(self.reserved.len() < initial_len)
     }
     
     fn parse_network_base(network: &str) -> Result<Ipv4Addr> {
@@ -83,55 +394,225 @@ impl ServerConfig {
             .with_context(|| format!("Invalid IP address in network: {}", parts[0]))
     }
     
-    fn server_ip_with_cidr(&self) -> Result<String> {
+    /// The server's tunnel address: the one chosen at `init`, or the first host
+    pub fn server_ip(&self) -> Result<Ipv4Addr> {
+        match self.server_ip {
+            Some(ip) => Ok(ip),
+            None => Ok(Ipv4Addr::from(u32::from(Self::parse_network_base(&self.network)?) + 1)),
+        }
+    }
+
+    /// Use `ip` as the server's tunnel address; it must be a host in the network
+    pub fn set_server_ip(&mut self, ip: Ipv4Addr) -> Result<()> {
+        let network = AddressRange::parse(&self.network)?;
+        if !network.contains(ip) || ip == network.start || ip == network.end {
+            anyhow::bail!("Server IP {} is not a host address in {}", ip, self.network);
+        }
+        if let Some(client) = self.clients.iter().find(|c| c.ip == IpAddr::V4(ip)) {
+            anyhow::bail!("IP {} is already assigned to '{}'", ip, client.name);
+        }
+        self.server_ip = Some(ip);
+        Ok(())
+    }
+
+    /// Move the server and every client to `network`, returning the plan.
+    ///
+    /// Addresses keep their offset from the start of the network where it
+    /// fits; clients whose offset does not fit get the next free address.
+    /// Pools and reserved ranges must fit, and AllowedIPs entries naming the
+    /// old network are rewritten.
+    pub fn renumber(&mut self, network: &str) -> Result<Vec<String>> {
+        let network = normalize_network(network)?;
+        if network == self.network {
+            anyhow::bail!("The network is already {}", network);
+        }
+        let old = AddressRange::parse(&self.network)?;
+        let new = AddressRange::parse(&network)?;
+        let shift = |ip: Ipv4Addr| -> Option<Ipv4Addr> {
+            if !old.contains(ip) {
+                return None;
+            }
+            let ip = Ipv4Addr::from(u32::from(new.start).checked_add(u32::from(ip) - u32::from(old.start))?);
+            new.contains(ip).then_some(ip)
+        };
+        let shift_host = |ip: Ipv4Addr| shift(ip).filter(|ip| *ip != new.start && *ip != new.end);
+        let mut plan = vec![format!("~ network {} -> {}", self.network, network)];
+
+        let mut pools = Vec::new();
+        for pool in &self.pools {
+            let range = pool.range()?;
+            let len = pool.network.split_once('/').map_or("32", |(_, len)| len);
+            let (Some(start), Some(_)) = (shift(range.start), shift(range.end)) else {
+                anyhow::bail!("Pool '{}' ({}) does not fit in {}; remove it first", pool.name, pool.network, network);
+            };
+            let moved = Pool::new(&pool.name, &format!("{}/{}", start, len))?;
+            plan.push(format!("~ pool '{}' {} -> {}", pool.name, pool.network, moved.network));
+            pools.push(moved);
+        }
+        let mut reserved = Vec::new();
+        for range in &self.reserved {
+            let parsed = AddressRange::parse(range)?;
+            let (Some(start), Some(end)) = (shift(parsed.start), shift(parsed.end)) else {
+                anyhow::bail!("Reserved range {} does not fit in {}; unreserve it first", range, network);
+            };
+            let moved = AddressRange { start, end }.to_string();
+            plan.push(format!("~ reserved {} -> {}", range, moved));
+            reserved.push(moved);
+        }
+
+        let old_server = self.server_ip()?;
+        let first_host = Ipv4Addr::from(u32::from(new.start) + 1);
+        let new_server = shift_host(old_server).unwrap_or(first_host);
+        plan.push(format!("~ server address {} -> {}", old_server, new_server));
+
+        let old_network = self.network.clone();
+        let mut moved_ips = HashMap::from([(old_server, new_server)]);
+        let mut unplaced = Vec::new();
+        for (i, client) in self.clients.iter_mut().enumerate() {
+            client.replace_route(&old_network, &network);
+            let IpAddr::V4(ip) = client.ip else { continue };
+            match shift_host(ip).filter(|ip| *ip != new_server) {
+                Some(moved) => {
+                    plan.push(format!("~ peer '{}' {} -> {}", client.name, ip, moved));
+                    client.ip = IpAddr::V4(moved);
+                    moved_ips.insert(ip, moved);
+                }
+                None => unplaced.push((i, ip)),
+            }
+        }
+
+        self.network = network;
+        self.server_ip = (new_server != first_host).then_some(new_server);
+        self.pools = pools;
+        self.reserved = reserved;
+        self.next_ip = shift(Ipv4Addr::from(self.next_ip)).map_or(u32::from(first_host), u32::from);
+        for (i, ip) in unplaced {
+            let moved = self.next_client_ip()?;
+            plan.push(format!("~ peer '{}' {} -> {} (offset does not fit)", self.clients[i].name, ip, moved));
+            self.clients[i].ip = moved;
+            if let IpAddr::V4(moved) = moved {
+                moved_ips.insert(ip, moved);
+            }
+        }
+
+        // Host routes and DNS servers in the old network follow their hosts
+        let remap = |ip: Ipv4Addr| moved_ips.get(&ip).copied().or_else(|| shift_host(ip));
+        for client in &mut self.clients {
+            let (allowed_ips, dns) = (client.allowed_ips.clone(), client.dns.clone());
+            client.remap_hosts(remap);
+            if client.allowed_ips != allowed_ips {
+                plan.push(format!("~ peer '{}' AllowedIPs {} -> {}", client.name, allowed_ips, client.allowed_ips));
+            }
+            if let (Some(old), Some(new)) = (dns, &client.dns)
+                && old != *new
+            {
+                plan.push(format!("~ peer '{}' DNS {} -> {}", client.name, old.join(", "), new.join(", ")));
+            }
+        }
+
+        Ok(plan)
+    }
+
+    pub fn server_ip_with_cidr(&self) -> Result<String> {
         let parts: Vec<&str> = self.network.split('/').collect();
         if parts.len() != 2 {
             anyhow::bail!("Invalid network format. Expected CIDR notation (e.g., 10.0.0.0/24)");
         }
         
-        let base_ip = Self::parse_network_base(&self.network)?;
-        let server_ip = Ipv4Addr::from(u32::from(base_ip) + 1);
-        Ok(format!("{}/{}", server_ip, parts[1]))
+        Ok(format!("{}/{}", self.server_ip()?, parts[1]))
     }
 }
 
-impl WireGuardConfig for ServerConfig {
-    fn to_wireguard_config(&self) -> Result<String> {
+impl ServerConfig {
+    /// The `[Interface]` section of the server config
+    fn interface_section(&self) -> Result<String> {
         let server_address = self.server_ip_with_cidr()?;
-        
-        let mut config = format!(
+        validate_interface(&self.interface)?;
+        if self.firewall == Firewall::Nftables {
+            return Ok(self.nftables_interface_section(&server_address));
+        }
+
+        Ok(format!(
 r#"[Interface]
 PrivateKey = {}
 Address = {}
 ListenPort = {}
 
 PostUp = sysctl -w net.ipv4.ip_forward=1
-PostUp = iptables -t nat -A POSTROUTING -o eth0 -j MASQUERADE
+PostUp = iptables -t nat -A POSTROUTING -o {} -j MASQUERADE
 PostUp = iptables -A FORWARD -i %i -j ACCEPT
 PostUp = iptables -A FORWARD -o %i -j ACCEPT
 
-PostDown = iptables -t nat -D POSTROUTING -o eth0 -j MASQUERADE
+PostDown = iptables -t nat -D POSTROUTING -o {} -j MASQUERADE
 PostDown = iptables -D FORWARD -i %i -j ACCEPT
 PostDown = iptables -D FORWARD -o %i -j ACCEPT
 "#,
             self.keys.private,
             server_address,
             self.port,
-            //self.interface,
-            //self.interface
-        );
-        
-        for client in &self.clients {
-            config.push_str(&format!(
-                "\n[Peer]\n\
+            self.interface,
+            self.interface
+        ))
+    }
+
+    /// The `[Interface]` section with NAT rules in a dedicated nftables table
+    fn nftables_interface_section(&self, server_address: &str) -> String {
+        format!(
+            "[Interface]\n\
+             PrivateKey = {key}\n\
+             Address = {address}\n\
+             ListenPort = {port}\n\
+             \n\
+             PostUp = sysctl -w net.ipv4.ip_forward=1\n\
+             PostUp = nft add table inet rusty-wire\n\
+             PostUp = nft add chain inet rusty-wire postrouting '{{ type nat hook postrouting priority srcnat; }}'\n\
+             PostUp = nft add rule inet rusty-wire postrouting oifname \"{wan}\" masquerade\n\
+             PostUp = nft add chain inet rusty-wire forward '{{ type filter hook forward priority filter; }}'\n\
+             PostUp = nft add rule inet rusty-wire forward iifname \"%i\" accept\n\
+             PostUp = nft add rule inet rusty-wire forward oifname \"%i\" accept\n\
+             \n\
+             PostDown = nft delete table inet rusty-wire\n",
+            key = self.keys.private,
+            address = server_address,
+            port = self.port,
+            wan = self.interface,
+        )
+    }
+
+    /// One `[Peer]` section per client
+    fn peers_section(&self) -> String {
+        let mut peers = String::new();
+        for (i, client) in self.clients.iter().enumerate() {
+            if i > 0 {
+                peers.push('\n');
+            }
+            peers.push_str(&client.annotation());
+            peers.push_str(&format!(
+                "[Peer]\n\
                  PublicKey = {}\n\
                  AllowedIPs = {}/32\n",
                 client.keys.public,
                 client.ip
             ));
         }
-        
-        Ok(config)
+        peers
+    }
+
+    /// The regions of `wg0.conf` owned by rusty-wire.
+    ///
+    /// Hand-written lines between the blocks stay in the `[Interface]`
+    /// section; manually added peers go after the peers block.
+    pub fn managed_blocks(&self) -> Result<Vec<Block>> {
+        Ok(vec![
+            Block::new("interface", self.interface_section()?),
+            Block::new("peers", self.peers_section()),
+        ])
+    }
+}
+
+impl WireGuardConfig for ServerConfig {
+    fn to_wireguard_config(&self) -> Result<String> {
+        Ok(managed::render(&self.managed_blocks()?))
     }
 }
 
@@ -142,7 +623,7 @@ impl ClientConfig {
         keys: KeyPair,
         server_endpoint: String,
         server_port: u16,
-        server_public_key: String,
+        server_public_key: PublicKey,
         allowed_ips: String,
     ) -> Self {
         Self {
@@ -153,8 +634,225 @@ impl ClientConfig {
             server_port,
             server_public_key,
             allowed_ips,
+            owner: None,
+            tags: Vec::new(),
+            created: Some(today()),
+            format: Format::default(),
+            template: None,
+            encrypt_to: Vec::new(),
+            dns: None,
+            keepalive: None,
+        }
+    }
+
+    /// Replace AllowedIPs entries naming the `old` network with `new`
+    pub fn replace_route(&mut self, old: &str, new: &str) {
+        let allowed_ips: Vec<String> = self
+            .allowed_ip_list()
+            .iter()
+            .map(|prefix| match normalize_network(prefix) {
+                Ok(prefix) if prefix == old => new.to_string(),
+                _ => prefix.to_string(),
+            })
+            .collect();
+        self.allowed_ips = allowed_ips.join(", ");
+    }
+
+    /// Rewrite single-address AllowedIPs entries and DNS servers with `remap`,
+    /// keeping those it returns `None` for
+    pub fn remap_hosts(&mut self, remap: impl Fn(Ipv4Addr) -> Option<Ipv4Addr>) {
+        let allowed_ips: Vec<String> = self
+            .allowed_ip_list()
+            .iter()
+            .map(|prefix| {
+                let (ip, len) = prefix.split_once('/').unwrap_or((prefix, ""));
+                match ip.parse().ok().filter(|_| matches!(len, "" | "32")).and_then(&remap) {
+                    Some(moved) if len.is_empty() => moved.to_string(),
+                    Some(moved) => format!("{}/{}", moved, len),
+                    None => prefix.to_string(),
+                }
+            })
+            .collect();
+        self.allowed_ips = allowed_ips.join(", ");
+        for entry in self.dns.iter_mut().flatten() {
+            if let Some(moved) = entry.parse().ok().and_then(&remap) {
+                *entry = moved.to_string();
+            }
+        }
+    }
+
+    /// Individual prefixes in `allowed_ips`
+    pub fn allowed_ip_list(&self) -> Vec<&str> {
+        self.allowed_ips
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .collect()
+    }
+
+    /// Whether all traffic is routed through the tunnel
+    pub fn is_full_tunnel(&self) -> bool {
+        self.allowed_ip_list()
+            .iter()
+            .any(|ip| *ip == "0.0.0.0/0" || *ip == "::/0")
+    }
+
+    pub fn dns(&self) -> Vec<&str> {
+        match &self.dns {
+            Some(dns) => dns.iter().map(String::as_str).collect(),
+            None => CLIENT_DNS.to_vec(),
+        }
+    }
+
+    /// The `dns()` entries that are server addresses
+    pub fn dns_servers(&self) -> Vec<&str> {
+        self.dns().into_iter().filter(|entry| entry.parse::<IpAddr>().is_ok()).collect()
+    }
+
+    /// The `dns()` entries that are search domains
+    pub fn dns_search(&self) -> Vec<&str> {
+        self.dns().into_iter().filter(|entry| entry.parse::<IpAddr>().is_err()).collect()
+    }
+
+    pub fn keepalive(&self) -> u16 {
+        self.keepalive.unwrap_or(DEFAULT_KEEPALIVE)
+    }
+
+    /// Check the free-text fields copied into config comments.
+    ///
+    /// A newline would end the comment and turn the rest into a directive.
+    pub fn validate(&self) -> Result<()> {
+        if let Some(owner) = &self.owner {
+            validate_text("Owner", owner)?;
+        }
+        for tag in &self.tags {
+            validate_text("Tag", tag)?;
         }
+        for entry in self.dns.iter().flatten() {
+            validate_dns(entry)?;
+        }
+        Ok(())
+    }
+
+    /// Comment lines identifying this client, ignored by wg-quick
+    pub fn annotation(&self) -> String {
+        let mut comment = format!("# Name: {}\n", self.name);
+        if let Some(owner) = &self.owner {
+            comment.push_str(&format!("# Owner: {}\n", owner));
+        }
+        if !self.tags.is_empty() {
+            comment.push_str(&format!("# Tags: {}\n", self.tags.join(", ")));
+        }
+        if let Some(created) = &self.created {
+            comment.push_str(&format!("# Created: {}\n", created));
+        }
+        comment
+    }
+
+    /// Short human-readable label, e.g. `laptop, alice` or `laptop`
+    pub fn label(&self) -> String {
+        match &self.owner {
+            Some(owner) => format!("{}, {}", self.name, owner),
+            None => self.name.clone(),
+        }
+    }
+}
+
+/// Check that `name` can be used as a client name, which also names its files
+pub fn validate_name(name: &str) -> Result<()> {
+    if name.trim().is_empty() {
+        anyhow::bail!("Client name must not be empty");
+    }
+    if !context::is_safe_name(name) {
+        anyhow::bail!(
+            "Invalid client name '{}': use letters, digits, '-', '_' and '.', not starting with '.'",
+            name.escape_default()
+        );
+    }
+    if RESERVED_NAMES.contains(&name) {
+        anyhow::bail!(
+            "Client name '{}' is reserved: 'rusty-wire client {}' runs a subcommand",
+            name,
+            name
+        );
     }
+    Ok(())
+}
+
+/// Check a network interface name, which ends up in PostUp/PostDown shell commands
+pub fn validate_interface(name: &str) -> Result<()> {
+    if name.is_empty()
+        || name.len() > 15
+        || !name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    {
+        anyhow::bail!(
+            "Invalid interface name '{}': use up to 15 letters, digits, '-', '_' and '.'",
+            name.escape_default()
+        );
+    }
+    Ok(())
+}
+
+/// Check a `DNS =` entry: an IP address or a search domain
+pub fn validate_dns(entry: &str) -> Result<()> {
+    let is_domain = entry.len() <= 253
+        && entry.trim_end_matches('.').split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+    if entry.parse::<IpAddr>().is_err() && !is_domain {
+        anyhow::bail!("Invalid DNS entry '{}': expected an IP address or domain", entry.escape_default());
+    }
+    Ok(())
+}
+
+fn validate_text(kind: &str, value: &str) -> Result<()> {
+    if value.chars().any(char::is_control) {
+        anyhow::bail!("{} {:?} must not contain control characters such as newlines", kind, value);
+    }
+    Ok(())
+}
+
+/// `network` with its host bits cleared, e.g. 10.0.0.5/24 becomes 10.0.0.0/24
+pub fn normalize_network(network: &str) -> Result<String> {
+    let Some((_, len)) = network.trim().split_once('/') else {
+        anyhow::bail!("Invalid network format. Expected CIDR notation (e.g., 10.0.0.0/24)");
+    };
+    let range = AddressRange::parse(network)?;
+    let len: u32 = len.parse()?;
+    if len > MAX_PREFIX_LEN {
+        anyhow::bail!(
+            "Network {} has no room for clients; use a /{} or larger",
+            network,
+            MAX_PREFIX_LEN
+        );
+    }
+    Ok(format!("{}/{}", range.start, len))
+}
+
+/// Today's date in UTC as YYYY-MM-DD
+pub fn today() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Convert days since 1970-01-01 to a (year, month, day) civil date
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    // Howard Hinnant's algorithm: shift the epoch to 0000-03-01
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
 }
 
 impl WireGuardConfig for ClientConfig {
@@ -163,23 +861,57 @@ impl WireGuardConfig for ClientConfig {
             "[Interface]\n\
              PrivateKey = {}\n\
              Address = {}/32\n\
-             DNS = 1.1.1.1, 9.9.9.9\n\
+             DNS = {}\n\
              \n\
              [Peer]\n\
              PublicKey = {}\n\
              Endpoint = {}:{}\n\
              AllowedIPs = {}\n\
-             PersistentKeepalive = 25\n",
+             PersistentKeepalive = {}\n",
             self.keys.private,
             self.ip,
+            self.dns().join(", "),
             self.server_public_key,
             self.server_endpoint,
             self.server_port,
-            self.allowed_ips
+            self.allowed_ips,
+            self.keepalive()
         ))
     }
 }
 
+/// Servers and clients shared by tests across modules
+#[cfg(test)]
+pub mod test_support {
+    use super::*;
+    use crate::crypto::generate_keypair;
+
+    /// A server at vpn.example.com:51820 on `network`, without clients
+    pub fn server_on(network: &str) -> ServerConfig {
+        ServerConfig::new(
+            "vpn.example.com".to_string(),
+            51820,
+            network.to_string(),
+            "eth0".to_string(),
+            generate_keypair().unwrap(),
+        )
+        .unwrap()
+    }
+
+    /// A server at vpn.example.com:51820 on 10.0.0.0/24, without clients
+    pub fn server() -> ServerConfig {
+        server_on("10.0.0.0/24")
+    }
+
+    /// Add a client with the next free IP and `allowed_ips` to `server`
+    pub fn client(server: &mut ServerConfig, name: &str, allowed_ips: &str) -> ClientConfig {
+        let mut client = server.new_client(name, None, None, false, generate_keypair().unwrap()).unwrap();
+        client.allowed_ips = allowed_ips.to_string();
+        server.add_client(&client).unwrap();
+        client
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -213,13 +945,136 @@ mod tests {
             keys,
         ).unwrap();
         
-        let ip1 = server.next_client_ip();
-        let ip2 = server.next_client_ip();
+        let ip1 = server.next_client_ip().unwrap();
+        let ip2 = server.next_client_ip().unwrap();
         
         assert_eq!(ip1.to_string(), "10.0.0.2");
         assert_eq!(ip2.to_string(), "10.0.0.3");
     }
+
+    #[test]
+    fn test_client_ips_do_not_collide() {
+        let mut server = test_support::server();
+        let ip = Some("10.0.0.2".parse().unwrap());
+
+        let manual = server.new_client("manual", ip, None, false, generate_keypair().unwrap()).unwrap();
+        server.add_client(&manual).unwrap();
+        assert_eq!(server.next_client_ip().unwrap().to_string(), "10.0.0.3");
+        let dup = server.new_client("dup", ip, None, false, generate_keypair().unwrap()).unwrap();
+        assert!(server.add_client(&dup).is_err());
+    }
+
+    #[test]
+    fn test_reserved_names() {
+        use clap::CommandFactory;
+
+        let mut command = crate::cli::Cli::command();
+        command.build();
+        let mut subcommands: Vec<&str> = command
+            .find_subcommand("client")
+            .unwrap()
+            .get_subcommands()
+            .map(|c| c.get_name())
+            .collect();
+        subcommands.sort();
+        let mut reserved = RESERVED_NAMES.to_vec();
+        reserved.sort();
+        assert_eq!(subcommands, reserved);
+
+        let mut server = test_support::server();
+        let client = server.new_client("show", None, None, false, generate_keypair().unwrap()).unwrap();
+        let error = server.add_client(&client).unwrap_err().to_string();
+        assert!(error.contains("reserved"), "{}", error);
+        test_support::client(&mut server, "laptop", "10.0.0.0/24");
+        assert!(server.rename_client("laptop", "move").is_err());
+    }
+
+    #[test]
+    fn test_client_names_are_file_names() {
+        let mut server = test_support::server();
+        for name in ["../x", "a/b", "line\nbreak", ".hidden", "tab\t", "ünïcode", ""] {
+            let client = server.new_client(name, None, None, false, generate_keypair().unwrap()).unwrap();
+            assert!(server.add_client(&client).is_err(), "{:?}", name);
+        }
+        assert!(server.clients.is_empty());
+        test_support::client(&mut server, "laptop-2_home.lan", "10.0.0.0/24");
+        assert!(server.rename_client("laptop-2_home.lan", "../b").is_err());
+    }
+
+    #[test]
+    fn test_rename_and_adopt_client() {
+        let mut home = test_support::server();
+        let mut work = test_support::server_on("10.2.0.0/24");
+        work.endpoint = "work.example.com".to_string();
+        for name in ["laptop", "phone"] {
+            test_support::client(&mut home, name, "10.0.0.0/24");
+        }
+
+        home.rename_client("laptop", "mac").unwrap();
+        assert_eq!(home.clients[0].name, "mac");
+        assert!(home.rename_client("mac", "phone").is_err());
+        assert!(home.rename_client("tablet", "ipad").is_err());
+        assert!(home.rename_client("mac", "../b").is_err());
+
+        // Names that only differ in case, '_' or '.' share a container name
+        assert!(home.rename_client("mac", "Phone").is_err());
+        home.rename_client("mac", "Mac").unwrap();
+        home.rename_client("Mac", "mac").unwrap();
+        let mut other = home.new_client("phone_", None, None, false, crate::crypto::generate_keypair().unwrap()).unwrap();
+        assert!(home.add_client(&other).is_err());
+        other.name = "phone_2".to_string();
+        home.add_client(&other).unwrap();
+
+        let moved = work.adopt_client(&home.clients[0], &home.network).unwrap();
+        assert_eq!(moved.ip.to_string(), "10.2.0.2");
+        assert_eq!(moved.keys.public, home.clients[0].keys.public);
+        assert_eq!(moved.server_endpoint, "work.example.com");
+        assert_eq!(moved.server_public_key, work.keys.public);
+        assert_eq!(moved.allowed_ips, "10.2.0.0/24");
+        assert!(work.adopt_client(&home.clients[0], &home.network).is_err());
+    }
     
+    #[test]
+    fn test_pools_and_reserved_ranges() {
+        let mut server = test_support::server();
+        server.add_pool("staff", "10.0.0.0/25").unwrap();
+        server.add_pool("iot", "10.0.0.128/26").unwrap();
+        assert!(server.add_pool("more", "10.0.0.64/26").is_err());
+        assert!(server.add_pool("outside", "10.1.0.0/26").is_err());
+        assert!(server.add_pool("../x", "10.0.0.192/27").is_err());
+        assert!(server.add_pool("a b", "10.0.0.192/27").is_err());
+        server.reserve("10.0.0.2-10.0.0.3").unwrap();
+        assert!(server.reserve("10.1.0.1").is_err());
+        let overlaps = server.reserved_in_pools().unwrap();
+        assert_eq!(overlaps.len(), 1);
+        assert_eq!((overlaps[0].0.name.as_str(), overlaps[0].1), ("staff", "10.0.0.2-10.0.0.3"));
+
+        let mut add = |pool: Option<&str>| {
+            let client = server
+                .new_client("c", None, pool, false, generate_keypair().unwrap())
+                .unwrap();
+            client.ip.to_string()
+        };
+        assert_eq!(add(Some("staff")), "10.0.0.4");
+        assert_eq!(add(Some("iot")), "10.0.0.128");
+        assert_eq!(add(None), "10.0.0.192");
+        assert!(server.new_client("c", None, Some("nope"), false, generate_keypair().unwrap()).is_err());
+
+        assert!(server.unreserve("10.0.0.2 - 10.0.0.3").unwrap());
+        assert!(server.reserved.is_empty());
+    }
+
+    #[test]
+    fn test_next_client_ip_wraps_around() {
+        let mut server = test_support::server_on("10.0.0.0/29");
+        for name in ["a", "b", "c", "d", "e"] {
+            test_support::client(&mut server, name, "10.0.0.0/29");
+        }
+        assert!(server.next_client_ip().is_err());
+        server.remove_client("b").unwrap();
+        assert_eq!(server.next_client_ip().unwrap().to_string(), "10.0.0.3");
+    }
+
     #[test]
     fn test_wireguard_config_generation() {
         let keys = generate_keypair().unwrap();
@@ -238,8 +1093,88 @@ mod tests {
         assert!(config.contains("Address = 10.0.0.1/24"));  // Should have CIDR notation
         assert!(config.contains("ListenPort = 51820"));
         assert!(config.contains("iptables"));
+        assert!(config.contains("# BEGIN rusty-wire interface"));
+        assert!(config.contains("# END rusty-wire peers"));
     }
     
+    #[test]
+    fn test_nftables_rules() {
+        let mut server = test_support::server();
+        server.interface = "ens3".to_string();
+        server.firewall = Firewall::Nftables;
+
+        let config = server.to_wireguard_config().unwrap();
+        assert!(config.contains("PostUp = nft add rule inet rusty-wire postrouting oifname \"ens3\" masquerade\n"));
+        assert!(config.contains("PostDown = nft delete table inet rusty-wire\n"));
+        assert!(!config.contains("iptables"));
+    }
+
+    #[test]
+    fn test_iptables_rules_use_interface() {
+        let mut server = test_support::server();
+        server.interface = "ens3".to_string();
+        let config = server.to_wireguard_config().unwrap();
+        assert!(config.contains("PostUp = iptables -t nat -A POSTROUTING -o ens3 -j MASQUERADE\n"));
+        assert!(config.contains("PostDown = iptables -t nat -D POSTROUTING -o ens3 -j MASQUERADE\n"));
+        assert!(!config.contains("eth0"));
+
+        server.interface = "eth0; rm -rf /".to_string();
+        assert!(server.to_wireguard_config().is_err());
+    }
+
+    #[test]
+    fn test_validate_interface_and_dns() {
+        for name in ["eth0", "ens3", "br-lan", "eth0.100"] {
+            assert!(validate_interface(name).is_ok(), "{}", name);
+        }
+        for name in ["", "eth0 -j ACCEPT", "eth0\nPostUp = x", "\"eth0\"", "averyveryverylongname"] {
+            assert!(validate_interface(name).is_err(), "{}", name);
+        }
+        for entry in ["1.1.1.1", "fd00::1", "corp.example.com", "lan"] {
+            assert!(validate_dns(entry).is_ok(), "{}", entry);
+        }
+        for entry in ["", "1.1.1.1\nPostUp = x", "a b", "a..b"] {
+            assert!(validate_dns(entry).is_err(), "{}", entry);
+        }
+
+        let mut server = test_support::server();
+        let mut client = server.new_client("a", None, None, false, generate_keypair().unwrap()).unwrap();
+        client.dns = Some(vec!["1.1.1.1\nPostUp = x".to_string()]);
+        assert!(server.add_client(&client).is_err());
+    }
+
+    #[test]
+    fn test_peer_annotations() {
+        let mut server = test_support::server();
+        test_support::client(&mut server, "laptop", "10.0.0.0/24");
+        let client = &mut server.clients[0];
+        client.owner = Some("alice".to_string());
+        client.tags = vec!["staff".to_string(), "remote".to_string()];
+        client.created = Some("2025-01-31".to_string());
+        let mut client = client.clone();
+
+        let config = server.to_wireguard_config().unwrap();
+        assert!(config.contains(
+            "# Name: laptop\n# Owner: alice\n# Tags: staff, remote\n# Created: 2025-01-31\n[Peer]\n"
+        ));
+
+        // A newline would turn the rest of a comment into a directive
+        client.name = "phone".to_string();
+        client.ip = server.next_client_ip().unwrap();
+        client.owner = Some("bob\nPostUp = touch /tmp/pwned".to_string());
+        assert!(server.add_client(&client).is_err());
+        client.owner = None;
+        client.tags = vec!["ok".to_string(), "bad\r".to_string()];
+        assert!(server.add_client(&client).is_err());
+    }
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+        assert_eq!(civil_from_days(20_483), (2026, 1, 30));
+    }
+
     #[test]
     fn test_server_ip_with_cidr() {
         let keys = generate_keypair().unwrap();
@@ -254,4 +1189,142 @@ mod tests {
         let server_ip = server.server_ip_with_cidr().unwrap();
         assert_eq!(server_ip, "10.0.0.1/24");
     }
+
+    #[test]
+    fn test_network_normalization() {
+        assert_eq!(normalize_network("10.0.0.5/24").unwrap(), "10.0.0.0/24");
+        assert_eq!(normalize_network("172.16.9.1/16").unwrap(), "172.16.0.0/16");
+        assert!(normalize_network("10.0.0.0/31").is_err());
+        assert!(normalize_network("10.0.0.0").is_err());
+        assert!(normalize_network("10.0.0.300/24").is_err());
+    }
+
+    #[test]
+    fn test_small_networks_and_server_ip() {
+        // A /30 has exactly one client address
+        let mut tiny = test_support::server_on("10.9.0.1/30");
+        assert_eq!(tiny.server_ip_with_cidr().unwrap(), "10.9.0.1/30");
+        assert_eq!(test_support::client(&mut tiny, "a", "10.9.0.0/30").ip.to_string(), "10.9.0.2");
+        assert!(tiny.next_client_ip().is_err());
+
+        // The server can sit anywhere; clients fill the space around it
+        let mut custom = test_support::server_on("10.0.0.0/28");
+        custom.set_server_ip("10.0.0.14".parse().unwrap()).unwrap();
+        assert_eq!(custom.server_ip_with_cidr().unwrap(), "10.0.0.14/28");
+        assert_eq!(custom.next_client_ip().unwrap().to_string(), "10.0.0.1");
+        assert!(custom.set_server_ip("10.0.0.15".parse().unwrap()).is_err());
+        assert!(custom.set_server_ip("10.0.1.1".parse().unwrap()).is_err());
+
+        let mut wide = test_support::server_on("10.1.0.0/16");
+        for _ in 0..300 {
+            wide.next_client_ip().unwrap();
+        }
+        assert_eq!(wide.next_client_ip().unwrap().to_string(), "10.1.1.46");
+    }
+
+    #[test]
+    fn test_renumber() {
+        let mut server = test_support::server();
+        server.reserve("10.0.0.100-10.0.0.109").unwrap();
+        for (name, ip) in [("laptop", "10.0.0.2"), ("phone", "10.0.0.200")] {
+            let mut client = server
+                .new_client(name, Some(ip.parse().unwrap()), None, false, generate_keypair().unwrap())
+                .unwrap();
+            client.allowed_ips = "10.0.0.0/24, 192.168.1.0/24".to_string();
+            server.add_client(&client).unwrap();
+        }
+        let mut v6 = server
+            .new_client("v6", Some("fd00::2".parse().unwrap()), None, false, generate_keypair().unwrap())
+            .unwrap();
+        v6.allowed_ips = "10.0.0.0/24, 10.0.0.1/32, 10.0.0.200/32, 10.0.0.0/25".to_string();
+        v6.dns = Some(vec!["10.0.0.1".to_string(), "1.1.1.1".to_string()]);
+        server.add_client(&v6).unwrap();
+
+        let plan = server.renumber("10.77.0.9/25").unwrap();
+        assert_eq!(plan[0], "~ network 10.0.0.0/24 -> 10.77.0.0/25");
+        assert!(plan.contains(&"~ reserved 10.0.0.100-10.0.0.109 -> 10.77.0.100-10.77.0.109".to_string()));
+        assert!(plan.contains(&"~ peer 'phone' 10.0.0.200 -> 10.77.0.3 (offset does not fit)".to_string()));
+        assert_eq!(server.server_ip_with_cidr().unwrap(), "10.77.0.1/25");
+        assert_eq!(server.clients[0].ip.to_string(), "10.77.0.2");
+        assert_eq!(server.clients[0].allowed_ips, "10.77.0.0/25, 192.168.1.0/24");
+        // The IPv6 client keeps its address, but its routes and DNS follow the hosts
+        assert_eq!(server.clients[2].ip.to_string(), "fd00::2");
+        assert_eq!(server.clients[2].allowed_ips, "10.77.0.0/25, 10.77.0.1/32, 10.77.0.3/32, 10.0.0.0/25");
+        assert_eq!(server.clients[2].dns, Some(vec!["10.77.0.1".to_string(), "1.1.1.1".to_string()]));
+        assert!(plan.contains(&"~ peer 'v6' DNS 10.0.0.1, 1.1.1.1 -> 10.77.0.1, 1.1.1.1".to_string()));
+        assert!(server.renumber("10.77.0.0/25").is_err());
+        assert!(server.renumber("10.78.0.0/26").is_err());
+    }
+
+    #[test]
+    fn test_baseline_wg0_conf_is_adopted() {
+        // State and wg0.conf exactly as the first release wrote them
+        let state = r#"{
+  "endpoint": "vpn.example.com",
+  "port": 51820,
+  "network": "10.0.0.0/24",
+  "interface": "eth0",
+  "keys": {
+    "public": "tkBkigArafbsbAqZYN9c0z9SwBL8eshXesp5Ac4XHkw=",
+    "private": "GelUDvJF4B0mAqRG61KWKHTaEWuK0cRXIq6dbXLzAyM="
+  },
+  "clients": [
+    {
+      "name": "laptop",
+      "ip": "10.0.0.2",
+      "keys": {
+        "public": "pIPTxEFQtlAZatXjZ0Mt7Rs8JnMDzQUOv0Nj5wnp7Eg=",
+        "private": "8BtfjAggkDLf4fWiWEm/HUJAo0STnuNV8uivJSiQzso="
+      },
+      "server_endpoint": "vpn.example.com",
+      "server_port": 51820,
+      "server_public_key": "tkBkigArafbsbAqZYN9c0z9SwBL8eshXesp5Ac4XHkw=",
+      "allowed_ips": "10.0.0.0/24"
+    },
+    {
+      "name": "phone",
+      "ip": "10.0.0.3",
+      "keys": {
+        "public": "NKRQFrzhuNCmXRLybCBGzc/zOiQUmJD2PTW4WrFmUQA=",
+        "private": "x4SUgo60i7vO62M0wd0GCKFYnQsRb5t/MrEpnvhQyOc="
+      },
+      "server_endpoint": "vpn.example.com",
+      "server_port": 51820,
+      "server_public_key": "tkBkigArafbsbAqZYN9c0z9SwBL8eshXesp5Ac4XHkw=",
+      "allowed_ips": "10.0.0.0/24"
+    }
+  ],
+  "next_ip": 167772164
+}"#;
+        let wg0 = "[Interface]
+PrivateKey = GelUDvJF4B0mAqRG61KWKHTaEWuK0cRXIq6dbXLzAyM=
+Address = 10.0.0.1/24
+ListenPort = 51820
+
+PostUp = sysctl -w net.ipv4.ip_forward=1
+PostUp = iptables -t nat -A POSTROUTING -o eth0 -j MASQUERADE
+PostUp = iptables -A FORWARD -i %i -j ACCEPT
+PostUp = iptables -A FORWARD -o %i -j ACCEPT
+
+PostDown = iptables -t nat -D POSTROUTING -o eth0 -j MASQUERADE
+PostDown = iptables -D FORWARD -i %i -j ACCEPT
+PostDown = iptables -D FORWARD -o %i -j ACCEPT
+
+[Peer]
+PublicKey = pIPTxEFQtlAZatXjZ0Mt7Rs8JnMDzQUOv0Nj5wnp7Eg=
+AllowedIPs = 10.0.0.2/32
+
+[Peer]
+PublicKey = NKRQFrzhuNCmXRLybCBGzc/zOiQUmJD2PTW4WrFmUQA=
+AllowedIPs = 10.0.0.3/32
+";
+        let mut server: ServerConfig = serde_json::from_str(state).unwrap();
+        let saved = server.managed_blocks().unwrap();
+        test_support::client(&mut server, "tablet", "10.0.0.0/24");
+        let blocks = server.managed_blocks().unwrap();
+        assert_eq!(managed::merge(wg0, &saved, &blocks).unwrap(), server.to_wireguard_config().unwrap());
+
+        let edited = wg0.replace("ListenPort = 51820\n", "ListenPort = 51820\nMTU = 1380\n");
+        assert!(managed::merge(&edited, &saved, &blocks).is_err());
+    }
 }
\ No newline at end of file
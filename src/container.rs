@@ -0,0 +1,217 @@
+use anyhow::Result;
+use base64::{Engine, engine::general_purpose::STANDARD};
+
+/// Container image used in compose snippets
+const WIREGUARD_IMAGE: &str = "lscr.io/linuxserver/wireguard:latest";
+/// Where the WireGuard image expects its wg-quick config
+const IMAGE_CONFIG_PATH: &str = "/config/wg_confs/wg0.conf";
+
+/// Quote a scalar for YAML; JSON strings are valid YAML flow scalars
+fn yaml_quote(value: &str) -> String {
+    serde_json::Value::String(value.to_string()).to_string()
+}
+
+/// Check that `name` is a DNS-1123 label: at most 63 lowercase letters,
+/// digits and '-', starting and ending with a letter or digit
+pub fn validate_dns_label(kind: &str, name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name.len() <= 63
+        && name.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+        && !name.starts_with('-')
+        && !name.ends_with('-');
+    if !valid {
+        anyhow::bail!(
+            "Invalid {} '{}'. Use at most 63 lowercase letters, digits and '-', \
+             starting and ending with a letter or digit",
+            kind,
+            name.escape_default()
+        );
+    }
+    Ok(())
+}
+
+/// The DNS-1123 label closest to `name`: lowercased, with '_' and '.' as '-',
+/// cut to 63 characters and without leading or trailing '-'
+pub fn dns_label(name: &str) -> String {
+    let label: String = name
+        .chars()
+        .map(|c| match c {
+            '_' | '.' => '-',
+            c => c.to_ascii_lowercase(),
+        })
+        .take(63)
+        .collect();
+    label.trim_matches('-').to_string()
+}
+
+/// Check that `name` is a DNS-1123 subdomain: dot-separated labels, at most 253 characters
+pub fn validate_dns_subdomain(kind: &str, name: &str) -> Result<()> {
+    if name.len() > 253 || name.split('.').any(|label| validate_dns_label(kind, label).is_err()) {
+        anyhow::bail!(
+            "Invalid {} '{}'. Use lowercase letters, digits, '-' and '.', \
+             with each part starting and ending with a letter or digit",
+            kind,
+            name.escape_default()
+        );
+    }
+    Ok(())
+}
+
+/// Parse `key=value` label arguments
+pub fn parse_labels(labels: &[String]) -> Result<Vec<(String, String)>> {
+    labels
+        .iter()
+        .map(|label| match label.split_once('=') {
+            Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+            _ => anyhow::bail!("Invalid label '{}'. Expected key=value", label),
+        })
+        .collect()
+}
+
+/// Wrap a wg-quick config into a Kubernetes `Secret` manifest.
+///
+/// The config is stored base64-encoded under `data` so no YAML escaping of
+/// the config itself is needed.
+pub fn kubernetes_secret(
+    name: &str,
+    namespace: Option<&str>,
+    labels: &[(String, String)],
+    key: &str,
+    config: &str,
+) -> Result<String> {
+    validate_dns_subdomain("Secret name", name)?;
+    if let Some(namespace) = namespace {
+        validate_dns_label("namespace", namespace)?;
+    }
+    let mut manifest = format!(
+        "apiVersion: v1\n\
+         kind: Secret\n\
+         metadata:\n\
+         \x20 name: {}\n",
+        yaml_quote(name)
+    );
+    if let Some(namespace) = namespace {
+        manifest.push_str(&format!("  namespace: {}\n", yaml_quote(namespace)));
+    }
+    manifest.push_str("  labels:\n    app.kubernetes.io/managed-by: rusty-wire\n");
+    for (label, value) in labels {
+        manifest.push_str(&format!("    {}: {}\n", yaml_quote(label), yaml_quote(value)));
+    }
+    manifest.push_str(&format!(
+        "type: Opaque\n\
+         data:\n\
+         \x20 {}: {}\n",
+        yaml_quote(key),
+        STANDARD.encode(config)
+    ));
+    Ok(manifest)
+}
+
+/// Render a docker-compose service running the WireGuard image with `config`.
+///
+/// The config is embedded as an inline compose `configs` entry, so the
+/// snippet is self-contained. Servers also publish their listen port.
+/// `name` names the service, its container and its config.
+pub fn compose_service(name: &str, config: &str, listen_port: Option<u16>) -> Result<String> {
+    validate_dns_label("container name", name)?;
+    let name = yaml_quote(name);
+    let mut compose = format!(
+        "services:\n\
+         \x20 {name}:\n\
+         \x20   image: {image}\n\
+         \x20   container_name: {name}\n\
+         \x20   cap_add:\n\
+         \x20     - NET_ADMIN\n\
+         \x20   sysctls:\n\
+         \x20     - net.ipv4.conf.all.src_valid_mark=1\n",
+        name = name,
+        image = WIREGUARD_IMAGE,
+    );
+    if let Some(port) = listen_port {
+        compose.push_str(&format!(
+            "      - net.ipv4.ip_forward=1\n\
+             \x20   ports:\n\
+             \x20     - \"{port}:{port}/udp\"\n",
+            port = port
+        ));
+    }
+    compose.push_str(&format!(
+        "    configs:\n\
+         \x20     - source: {name}\n\
+         \x20       target: {target}\n\
+         \x20       mode: 0600\n\
+         \x20   restart: unless-stopped\n\
+         \n\
+         configs:\n\
+         \x20 {name}:\n\
+         \x20   content: |\n",
+        name = name,
+        target = IMAGE_CONFIG_PATH,
+    ));
+    for line in config.lines() {
+        if line.is_empty() {
+            compose.push('\n');
+        } else {
+            compose.push_str(&format!("      {}\n", line));
+        }
+    }
+    Ok(compose)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "[Interface]\nPrivateKey = abc\n\n[Peer]\nPublicKey = def\n";
+
+    #[test]
+    fn test_kubernetes_secret() {
+        let labels = parse_labels(&["team=net".to_string()]).unwrap();
+        let manifest =
+            kubernetes_secret("wg-laptop", Some("vpn"), &labels, "wg0.conf", CONFIG).unwrap();
+
+        assert!(manifest.starts_with("apiVersion: v1\nkind: Secret\nmetadata:\n  name: \"wg-laptop\"\n"));
+        assert!(manifest.contains("  namespace: \"vpn\"\n"));
+        assert!(manifest.contains("    \"team\": \"net\"\n"));
+        assert!(manifest.contains(&format!("  \"wg0.conf\": {}\n", STANDARD.encode(CONFIG))));
+    }
+
+    #[test]
+    fn test_parse_labels_rejects_missing_value() {
+        assert!(parse_labels(&["team".to_string()]).is_err());
+        assert!(parse_labels(&["=net".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_compose_service() {
+        let client = compose_service("wg-laptop", CONFIG, None).unwrap();
+        assert!(client.starts_with("services:\n  \"wg-laptop\":\n"));
+        assert!(client.contains("container_name: \"wg-laptop\"\n"));
+        assert!(client.contains("image: lscr.io/linuxserver/wireguard:latest\n"));
+        assert!(client.contains("target: /config/wg_confs/wg0.conf\n"));
+        assert!(client.contains("    content: |\n      [Interface]\n      PrivateKey = abc\n\n      [Peer]\n"));
+        assert!(!client.contains("ports:"));
+
+        let server = compose_service("wg-server", CONFIG, Some(51820)).unwrap();
+        assert!(server.contains("      - \"51820:51820/udp\"\n"));
+    }
+
+    #[test]
+    fn test_container_names_are_dns_labels() {
+        assert!(compose_service("wg-Laptop", CONFIG, None).is_err());
+        assert!(compose_service("wg-a_b", CONFIG, None).is_err());
+        assert!(compose_service("wg-a\n  evil:", CONFIG, None).is_err());
+        assert!(compose_service(&format!("wg-{}", "a".repeat(61)), CONFIG, None).is_err());
+        assert!(kubernetes_secret("wg.laptop", None, &[], "wg0.conf", CONFIG).is_ok());
+        assert!(kubernetes_secret("wg..laptop", None, &[], "wg0.conf", CONFIG).is_err());
+        assert!(kubernetes_secret("wg-laptop", Some("a.b"), &[], "wg0.conf", CONFIG).is_err());
+    }
+
+    #[test]
+    fn test_dns_label() {
+        assert_eq!(dns_label("wg-Laptop_2.home"), "wg-laptop-2-home");
+        assert_eq!(dns_label("wg-phone_"), "wg-phone");
+        assert_eq!(dns_label(&format!("wg-{}", "a".repeat(70))).len(), 63);
+        assert!(validate_dns_label("name", &dns_label("wg-A.B_c-")).is_ok());
+    }
+}
@@ -0,0 +1,147 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The context kept at the top of the home directory, as before contexts existed
+pub const DEFAULT: &str = "default";
+
+/// Server state file in each context directory
+pub const STATE_FILE: &str = "wg-server.json";
+
+/// Directory under the home directory holding named contexts
+const CONTEXTS_DIR: &str = "contexts";
+
+/// File in the home directory recording the context chosen with `context use`
+const CURRENT_FILE: &str = ".rusty-wire-context";
+
+/// Whether `name` is safe as a file or directory name: letters, digits,
+/// '-', '_' and '.', not starting with '.'
+pub fn is_safe_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Check that `name` can be used as a directory name
+pub fn validate_name(name: &str) -> Result<()> {
+    if !is_safe_name(name) {
+        anyhow::bail!(
+            "Invalid context name '{}': use letters, digits, '-', '_' and '.'",
+            name
+        );
+    }
+    Ok(())
+}
+
+/// Directory holding the state and configs of context `name`
+pub fn dir(home: &Path, name: &str) -> PathBuf {
+    if name == DEFAULT {
+        home.to_path_buf()
+    } else {
+        home.join(CONTEXTS_DIR).join(name)
+    }
+}
+
+/// Whether context `name` has been initialized
+pub fn exists(home: &Path, name: &str) -> bool {
+    dir(home, name).join(STATE_FILE).exists()
+}
+
+/// The context chosen with `context use`, or the default
+pub fn current(home: &Path) -> Result<String> {
+    let path = home.join(CURRENT_FILE);
+    if !path.exists() {
+        return Ok(DEFAULT.to_string());
+    }
+    let name = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {:?}", path))?
+        .trim()
+        .to_string();
+    validate_name(&name).with_context(|| format!("Invalid current context in {:?}", path))?;
+    Ok(name)
+}
+
+/// Make `name` the context used when none is given
+pub fn set_current(home: &Path, name: &str) -> Result<()> {
+    let path = home.join(CURRENT_FILE);
+    if name == DEFAULT {
+        if path.exists() {
+            fs::remove_file(&path).with_context(|| format!("Failed to remove {:?}", path))?;
+        }
+        return Ok(());
+    }
+    fs::write(&path, format!("{}\n", name)).with_context(|| format!("Failed to write {:?}", path))
+}
+
+/// The context to work in: `selected` if given, otherwise the current one
+pub fn resolve(home: &Path, selected: Option<&str>) -> Result<String> {
+    match selected {
+        Some(name) => {
+            validate_name(name)?;
+            Ok(name.to_string())
+        }
+        None => current(home),
+    }
+}
+
+/// Initialized contexts, the default first and the rest sorted by name
+pub fn list(home: &Path) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    let contexts = home.join(CONTEXTS_DIR);
+    if contexts.is_dir() {
+        for entry in fs::read_dir(&contexts).with_context(|| format!("Failed to read {:?}", contexts))? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if validate_name(&name).is_ok() && name != DEFAULT && exists(home, &name) {
+                names.push(name);
+            }
+        }
+    }
+    names.sort();
+    if exists(home, DEFAULT) {
+        names.insert(0, DEFAULT.to_string());
+    }
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn home(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rusty-wire-context-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_list_and_switch() {
+        let home = home("list");
+        assert!(list(&home).unwrap().is_empty());
+        assert_eq!(current(&home).unwrap(), DEFAULT);
+
+        fs::write(home.join(STATE_FILE), "{}").unwrap();
+        for name in ["work", "lab"] {
+            fs::create_dir_all(dir(&home, name)).unwrap();
+            fs::write(dir(&home, name).join(STATE_FILE), "{}").unwrap();
+        }
+        fs::create_dir_all(dir(&home, "empty")).unwrap();
+        assert_eq!(list(&home).unwrap(), ["default", "lab", "work"]);
+
+        set_current(&home, "work").unwrap();
+        assert_eq!(resolve(&home, None).unwrap(), "work");
+        assert_eq!(resolve(&home, Some("lab")).unwrap(), "lab");
+        set_current(&home, DEFAULT).unwrap();
+        assert_eq!(current(&home).unwrap(), DEFAULT);
+
+        fs::remove_dir_all(&home).unwrap();
+    }
+
+    #[test]
+    fn test_validate_name() {
+        assert!(validate_name("work-2").is_ok());
+        assert!(validate_name("").is_err());
+        assert!(validate_name("..").is_err());
+        assert!(validate_name("a/b").is_err());
+    }
+}
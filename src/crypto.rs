@@ -1,26 +1,282 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use base64::{Engine, engine::general_purpose::STANDARD};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Mutex, mpsc};
+use std::thread;
+use std::time::Duration;
 use x25519_dalek::StaticSecret;
+use zeroize::{Zeroize, ZeroizeOnDrop};
 
+/// Length in bytes of every WireGuard key
+pub const KEY_LEN: usize = 32;
+
+/// Number of SHA-256 bytes in a key fingerprint (8 base64 characters)
+const FINGERPRINT_BYTES: usize = 6;
+
+/// Decode a base64 WireGuard key into its raw 32 bytes
+fn decode_key(s: &str, kind: &str) -> Result<[u8; KEY_LEN]> {
+    let mut bytes = STANDARD
+        .decode(s.trim())
+        .with_context(|| format!("Invalid {}: not valid base64", kind))?;
+    if bytes.len() != KEY_LEN {
+        let len = bytes.len();
+        bytes.zeroize();
+        anyhow::bail!("Invalid {}: expected {} bytes, got {}", kind, KEY_LEN, len);
+    }
+    let mut key = [0u8; KEY_LEN];
+    key.copy_from_slice(&bytes);
+    bytes.zeroize();
+    Ok(key)
+}
+
+/// Implement base64 `Display`/`FromStr` and string (de)serialization for a key newtype
+macro_rules! impl_key_encoding {
+    ($ty:ident, $kind:literal) => {
+        impl From<[u8; KEY_LEN]> for $ty {
+            fn from(bytes: [u8; KEY_LEN]) -> Self {
+                Self(bytes)
+            }
+        }
+
+        impl FromStr for $ty {
+            type Err = anyhow::Error;
+
+            fn from_str(s: &str) -> Result<Self> {
+                decode_key(s, $kind).map(Self)
+            }
+        }
+
+        impl fmt::Display for $ty {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&STANDARD.encode(self.0))
+            }
+        }
+
+        impl Serialize for $ty {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(&self.to_string())
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $ty {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let mut s = String::deserialize(deserializer)?;
+                let key = s.parse().map_err(serde::de::Error::custom);
+                s.zeroize();
+                key
+            }
+        }
+    };
+}
+
+/// A WireGuard (X25519) public key
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PublicKey([u8; KEY_LEN]);
+
+/// A WireGuard (X25519) private key, zeroized on drop
+#[derive(Clone, PartialEq, Eq, Zeroize, ZeroizeOnDrop)]
+pub struct PrivateKey([u8; KEY_LEN]);
+
+/// A WireGuard preshared key, zeroized on drop
+#[derive(Clone, PartialEq, Eq, Zeroize, ZeroizeOnDrop)]
+pub struct PresharedKey([u8; KEY_LEN]);
+
+impl_key_encoding!(PublicKey, "public key");
+impl_key_encoding!(PrivateKey, "private key");
+impl_key_encoding!(PresharedKey, "preshared key");
+
+impl fmt::Debug for PublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PublicKey({})", self)
+    }
+}
+
+impl PublicKey {
+    /// Short identifier for a key: the start of its SHA-256 hash in base64.
+    ///
+    /// Hashing keeps vanity keys sharing a prefix distinguishable.
+    pub fn fingerprint(&self) -> String {
+        STANDARD.encode(&Sha256::digest(self.0)[..FINGERPRINT_BYTES])
+    }
+}
+
+impl fmt::Debug for PrivateKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PrivateKey(<redacted>)")
+    }
+}
+
+impl fmt::Debug for PresharedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PresharedKey(<redacted>)")
+    }
+}
+
+impl PrivateKey {
+    /// Generate a new random private key, clamped the same way as `wg genkey`
+    pub fn generate() -> Self {
+        let mut bytes = StaticSecret::random().to_bytes();
+        bytes[0] &= 248;
+        bytes[31] = (bytes[31] & 127) | 64;
+        let key = Self(bytes);
+        bytes.zeroize();
+        key
+    }
+
+    /// Derive the matching public key
+    pub fn public_key(&self) -> PublicKey {
+        let secret = StaticSecret::from(self.0);
+        PublicKey(x25519_dalek::PublicKey::from(&secret).to_bytes())
+    }
+}
+
+impl PresharedKey {
+    /// Generate a new random preshared key
+    pub fn generate() -> Self {
+        Self(StaticSecret::random().to_bytes())
+    }
+}
+
+/// A private key together with its derived public key.
+///
+/// Deserializing checks that the stored public key matches the private key,
+/// so a hand-edited state file cannot silently produce a broken config.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "RawKeyPair")]
 pub struct KeyPair {
-    pub public: String,
-    pub private: String,
+    pub public: PublicKey,
+    pub private: PrivateKey,
+}
+
+#[derive(Deserialize)]
+struct RawKeyPair {
+    public: PublicKey,
+    private: PrivateKey,
+}
+
+impl TryFrom<RawKeyPair> for KeyPair {
+    type Error = anyhow::Error;
+
+    fn try_from(raw: RawKeyPair) -> Result<Self> {
+        let derived = raw.private.public_key();
+        if derived != raw.public {
+            anyhow::bail!(
+                "Public key {} does not match private key (expected {})",
+                raw.public,
+                derived
+            );
+        }
+        Ok(Self {
+            public: raw.public,
+            private: raw.private,
+        })
+    }
+}
+
+impl From<PrivateKey> for KeyPair {
+    fn from(private: PrivateKey) -> Self {
+        Self {
+            public: private.public_key(),
+            private,
+        }
+    }
 }
 
 /// Generate a WireGuard-compatible X25519 keypair
 pub fn generate_keypair() -> Result<KeyPair> {
-    let private_key = StaticSecret::random();
-    let public_key = x25519_dalek::PublicKey::from(&private_key);
+    Ok(KeyPair::from(PrivateKey::generate()))
+}
+
+/// Characters that can appear in a base64-encoded key
+const BASE64_ALPHABET: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Base64 characters of a key before the '=' padding
+const KEY_CHARS: usize = 43;
+
+/// Key attempts each search thread makes between checks for a result
+const VANITY_BATCH: u64 = 256;
+
+/// Expected number of keypairs to try before finding a public key with `prefix`
+pub fn vanity_expected_attempts(prefix: &str) -> Result<f64> {
+    if prefix.is_empty() {
+        anyhow::bail!("Vanity prefix must not be empty");
+    }
+    if let Some(c) = prefix.chars().find(|c| !BASE64_ALPHABET.contains(*c)) {
+        anyhow::bail!("Vanity prefix contains '{}', which never appears in a base64 key", c);
+    }
+    // Only 43 characters carry key data; the final one is always '='
+    if prefix.len() > KEY_CHARS {
+        anyhow::bail!("Vanity prefix is longer than a public key");
+    }
+    // The 43rd character holds the last 4 bits followed by two zero bits
+    if prefix.len() == KEY_CHARS {
+        let last = prefix.chars().last().and_then(|c| BASE64_ALPHABET.find(c)).unwrap_or(0);
+        if !last.is_multiple_of(4) {
+            anyhow::bail!(
+                "Vanity prefix cannot end in '{}' at position {}; a key's last character is one of AEIMQUYcgkosw048",
+                &prefix[KEY_CHARS - 1..],
+                KEY_CHARS
+            );
+        }
+        return Ok(64f64.powi(KEY_CHARS as i32 - 1) * 16.0);
+    }
+    Ok(64f64.powi(prefix.len() as i32))
+}
+
+/// Search for a keypair whose base64 public key starts with `prefix`.
+///
+/// Runs one worker per available core and calls `progress` with the total
+/// number of attempts roughly every `interval` until a match is found.
+pub fn vanity_keypair(
+    prefix: &str,
+    interval: Duration,
+    mut progress: impl FnMut(u64),
+) -> Result<KeyPair> {
+    vanity_expected_attempts(prefix)?;
+    let workers = thread::available_parallelism().map_or(1, |n| n.get());
+    let found = AtomicBool::new(false);
+    let attempts = AtomicU64::new(0);
+    let result = Mutex::new(None);
 
-    let private_bytes = private_key.as_bytes();
-    let public_bytes = public_key.as_bytes();
+    thread::scope(|scope| {
+        let (done_tx, done_rx) = mpsc::channel::<()>();
+        for _ in 0..workers {
+            let done_tx = done_tx.clone();
+            let (found, attempts, result) = (&found, &attempts, &result);
+            scope.spawn(move || {
+                while !found.load(Ordering::Relaxed) {
+                    let mut tried = 0;
+                    for _ in 0..VANITY_BATCH {
+                        tried += 1;
+                        let keys = KeyPair::from(PrivateKey::generate());
+                        if keys.public.to_string().starts_with(prefix) {
+                            found.store(true, Ordering::Relaxed);
+                            if let Ok(mut slot) = result.lock() {
+                                slot.get_or_insert(keys);
+                            }
+                            let _ = done_tx.send(());
+                            break;
+                        }
+                    }
+                    attempts.fetch_add(tried, Ordering::Relaxed);
+                }
+            });
+        }
+        drop(done_tx);
+        while let Err(mpsc::RecvTimeoutError::Timeout) = done_rx.recv_timeout(interval) {
+            progress(attempts.load(Ordering::Relaxed));
+        }
+    });
 
-    Ok(KeyPair {
-        private: STANDARD.encode(private_bytes),
-        public: STANDARD.encode(public_bytes),
-    })
+    result
+        .into_inner()
+        .ok()
+        .flatten()
+        .context("Vanity search ended without a result")
 }
 
 #[cfg(test)]
@@ -31,14 +287,16 @@ mod tests {
     #[test]
     fn test_keypair_generation() {
         let keypair = generate_keypair().unwrap();
+        let private = keypair.private.to_string();
+        let public = keypair.public.to_string();
 
         // Keys should be valid base64
-        assert!(STANDARD.decode(&keypair.private).is_ok());
-        assert!(STANDARD.decode(&keypair.public).is_ok());
+        assert!(STANDARD.decode(&private).is_ok());
+        assert!(STANDARD.decode(&public).is_ok());
 
         // Keys should be 32 bytes when decoded
-        assert_eq!(STANDARD.decode(&keypair.private).unwrap().len(), 32);
-        assert_eq!(STANDARD.decode(&keypair.public).unwrap().len(), 32);
+        assert_eq!(STANDARD.decode(&private).unwrap().len(), 32);
+        assert_eq!(STANDARD.decode(&public).unwrap().len(), 32);
 
         // Should generate different keys each time
         let keypair2 = generate_keypair().unwrap();
@@ -49,13 +307,87 @@ mod tests {
     #[test]
     fn test_key_format() {
         let keypair = generate_keypair().unwrap();
+        let private = keypair.private.to_string();
+        let public = keypair.public.to_string();
 
         // WireGuard keys are typically 44 characters (32 bytes base64 encoded with padding)
-        assert_eq!(keypair.private.len(), 44);
-        assert_eq!(keypair.public.len(), 44);
+        assert_eq!(private.len(), 44);
+        assert_eq!(public.len(), 44);
 
         // Should end with = (base64 padding)
-        assert!(keypair.private.ends_with('=') || keypair.private.ends_with("=="));
-        assert!(keypair.public.ends_with('=') || keypair.public.ends_with("=="));
+        assert!(private.ends_with('=') || private.ends_with("=="));
+        assert!(public.ends_with('=') || public.ends_with("=="));
+    }
+
+    #[test]
+    fn test_key_parsing_rejects_invalid_input() {
+        assert!("not base64!".parse::<PublicKey>().is_err());
+        assert!(STANDARD.encode([0u8; 16]).parse::<PrivateKey>().is_err());
+        assert!(STANDARD.encode([7u8; 32]).parse::<PresharedKey>().is_ok());
+    }
+
+    #[test]
+    fn test_generated_private_key_is_clamped() {
+        let key = PrivateKey::generate();
+        assert_eq!(key.0[0] & 7, 0);
+        assert_eq!(key.0[31] & 128, 0);
+        assert_eq!(key.0[31] & 64, 64);
+    }
+
+    #[test]
+    fn test_fingerprint_hashes_key() {
+        let key: PublicKey = STANDARD.encode([1u8; 32]).parse().unwrap();
+        let fingerprint = key.fingerprint();
+        assert_eq!(fingerprint.len(), 8);
+        assert_eq!(fingerprint, STANDARD.encode(&Sha256::digest([1u8; 32])[..6]));
+
+        // Keys sharing a base64 prefix still get different fingerprints
+        let mut bytes = [1u8; 32];
+        bytes[31] = 2;
+        let similar: PublicKey = STANDARD.encode(bytes).parse().unwrap();
+        assert_eq!(key.to_string()[..40], similar.to_string()[..40]);
+        assert_ne!(similar.fingerprint(), fingerprint);
+    }
+
+    #[test]
+    fn test_vanity_prefix_validation() {
+        assert_eq!(vanity_expected_attempts("ab").unwrap(), 4096.0);
+        assert!(vanity_expected_attempts("").is_err());
+        assert!(vanity_expected_attempts("lab-").is_err());
+
+        // Only every fourth character can end a 43-character prefix
+        let full = "A".repeat(42);
+        assert!(vanity_expected_attempts(&format!("{}E", full)).is_ok());
+        assert!(vanity_expected_attempts(&format!("{}B", full)).is_err());
+        assert!(vanity_expected_attempts(&format!("{}AA", full)).is_err());
+    }
+
+    #[test]
+    fn test_vanity_keypair_matches_prefix() {
+        let keys = vanity_keypair("A", Duration::from_millis(10), |_| {}).unwrap();
+        assert!(keys.public.to_string().starts_with('A'));
+        assert_eq!(keys.private.public_key(), keys.public);
+    }
+
+    #[test]
+    fn test_debug_redacts_secrets() {
+        let keypair = generate_keypair().unwrap();
+        let debug = format!("{:?}", keypair);
+        assert!(debug.contains("<redacted>"));
+        assert!(!debug.contains(&keypair.private.to_string()));
+        assert!(debug.contains(&keypair.public.to_string()));
+    }
+
+    #[test]
+    fn test_keypair_mismatch_rejected_on_load() {
+        let a = generate_keypair().unwrap();
+        let b = generate_keypair().unwrap();
+        let json = format!(r#"{{"public":"{}","private":"{}"}}"#, b.public, a.private);
+        let err = serde_json::from_str::<KeyPair>(&json).unwrap_err();
+        assert!(err.to_string().contains("does not match"));
+
+        let json = serde_json::to_string(&a).unwrap();
+        let loaded: KeyPair = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.public, a.public);
     }
 }
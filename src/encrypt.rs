@@ -0,0 +1,81 @@
+use age::armor::{ArmoredWriter, Format as ArmorFormat};
+use age::x25519;
+use anyhow::{Context, Result};
+use std::io::Write;
+
+use crate::export::RenderedFile;
+
+/// Suffix added to the names of encrypted files
+pub const EXTENSION: &str = "age";
+
+/// Parse age recipients (`age1...`)
+fn parse_recipients(recipients: &[String]) -> Result<Vec<x25519::Recipient>> {
+    recipients
+        .iter()
+        .map(|recipient| {
+            recipient
+                .trim()
+                .parse::<x25519::Recipient>()
+                .map_err(|e| anyhow::anyhow!("Invalid age recipient '{}': {}", recipient, e))
+        })
+        .collect()
+}
+
+/// Encrypt `plaintext` to every recipient as an ASCII-armored age file
+pub fn encrypt(recipients: &[String], plaintext: &str) -> Result<String> {
+    let recipients = parse_recipients(recipients)?;
+    let encryptor = age::Encryptor::with_recipients(recipients.iter().map(|r| r as &dyn age::Recipient))
+        .context("No age recipients given")?;
+
+    let mut ciphertext = Vec::new();
+    let armor = ArmoredWriter::wrap_output(&mut ciphertext, ArmorFormat::AsciiArmor)?;
+    let mut writer = encryptor.wrap_output(armor)?;
+    writer.write_all(plaintext.as_bytes())?;
+    writer.finish()?.finish()?;
+    Ok(String::from_utf8(ciphertext).expect("armored output is ASCII"))
+}
+
+/// Encrypt rendered files, adding `.age` to their names
+pub fn encrypt_files(files: Vec<RenderedFile>, recipients: &[String]) -> Result<Vec<RenderedFile>> {
+    files
+        .into_iter()
+        .map(|file| {
+            Ok(RenderedFile {
+                contents: encrypt(recipients, &file.contents)?,
+                name: format!("{}.{}", file.name, EXTENSION),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_to_each_recipient() {
+        let alice = x25519::Identity::generate();
+        let bob = x25519::Identity::generate();
+        let recipients = [alice.to_public().to_string(), bob.to_public().to_string()];
+
+        let files = vec![RenderedFile {
+            name: "laptop.conf".to_string(),
+            contents: "[Interface]\nPrivateKey = abc\n".to_string(),
+        }];
+        let encrypted = encrypt_files(files, &recipients).unwrap();
+        assert_eq!(encrypted[0].name, "laptop.conf.age");
+        assert!(encrypted[0].contents.starts_with("-----BEGIN AGE ENCRYPTED FILE-----\n"));
+        assert!(!encrypted[0].contents.contains("PrivateKey"));
+
+        for identity in [&alice, &bob] {
+            let plaintext = age::decrypt(identity, encrypted[0].contents.as_bytes()).unwrap();
+            assert_eq!(plaintext, b"[Interface]\nPrivateKey = abc\n");
+        }
+    }
+
+    #[test]
+    fn test_invalid_recipient() {
+        assert!(encrypt(&["age1nope".to_string()], "x").is_err());
+        assert!(encrypt(&[], "x").is_err());
+    }
+}
@@ -0,0 +1,488 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::container;
+use crate::config::{ClientConfig, ServerConfig, WireGuardConfig};
+use crate::networkd::NetworkdConfig;
+use crate::router;
+
+/// Name of the WireGuard interface on clients, and on servers unless set
+pub const INTERFACE: &str = "wg0";
+
+/// Where systemd-networkd units and their key files are installed
+const NETWORKD_DIR: &str = "/etc/systemd/network";
+
+/// Output formats for rendered configs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Format {
+    /// wg-quick `.conf` file
+    #[default]
+    #[value(alias = "conf")]
+    WgQuick,
+    /// NetworkManager `.nmconnection` keyfile
+    #[value(alias = "networkmanager")]
+    Nm,
+    /// systemd-networkd `.netdev` and `.network` units
+    #[value(alias = "systemd-networkd")]
+    Networkd,
+    /// OpenWrt `uci batch` script
+    #[value(alias = "openwrt")]
+    Uci,
+    /// MikroTik RouterOS script
+    #[value(alias = "mikrotik")]
+    Routeros,
+    /// Kubernetes `Secret` manifest wrapping the wg-quick config
+    #[value(name = "k8s", alias = "kubernetes")]
+    #[serde(rename = "k8s")]
+    Kubernetes,
+    /// docker-compose service running a WireGuard container
+    #[value(alias = "docker-compose")]
+    Compose,
+}
+
+impl Format {
+    pub fn is_default(&self) -> bool {
+        *self == Format::default()
+    }
+
+    /// Whether servers can be rendered in this format, not just clients
+    pub fn is_server_format(&self) -> bool {
+        !matches!(self, Format::Nm | Format::Uci | Format::Routeros)
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.to_possible_value() {
+            Some(value) => f.write_str(value.get_name()),
+            None => write!(f, "{:?}", self),
+        }
+    }
+}
+
+/// A rendered config file, named relative to the output directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderedFile {
+    pub name: String,
+    pub contents: String,
+}
+
+/// Options that change how configs are rendered
+#[derive(Debug, Clone, Default)]
+pub struct ExportOptions {
+    /// Path the networkd netdev reads its private key from, instead of
+    /// `<ifname>.key` in the networkd directory
+    pub private_key_file: Option<PathBuf>,
+    /// Name of the Kubernetes Secret (defaults to `wg-<client>` or `wg0`)
+    pub secret_name: Option<String>,
+    /// Namespace of the Kubernetes Secret
+    pub namespace: Option<String>,
+    /// Extra labels on the Kubernetes Secret
+    pub labels: Vec<(String, String)>,
+    /// age recipients to encrypt the rendered files to
+    pub encrypt_to: Vec<String>,
+}
+
+/// Wrap a wg-quick config for interface `ifname` for a container platform
+fn container_file(
+    stem: &str,
+    ifname: &str,
+    default_name: String,
+    format: Format,
+    config: &str,
+    listen_port: Option<u16>,
+    options: &ExportOptions,
+) -> Result<RenderedFile> {
+    Ok(match format {
+        Format::Kubernetes => RenderedFile {
+            name: format!("{}.secret.yaml", stem),
+            contents: container::kubernetes_secret(
+                options.secret_name.as_deref().unwrap_or(&default_name),
+                options.namespace.as_deref(),
+                &options.labels,
+                &format!("{}.conf", ifname),
+                config,
+            )?,
+        },
+        Format::Compose => RenderedFile {
+            name: format!("{}.compose.yaml", stem),
+            contents: container::compose_service(&default_name, config, listen_port)?,
+        },
+        Format::WgQuick | Format::Nm | Format::Networkd | Format::Uci | Format::Routeros => {
+            anyhow::bail!("{:?} is not a container format", format)
+        }
+    })
+}
+
+/// Where the netdev for `ifname` reads its private key.
+///
+/// `prefix` is added to the file name so the rendered key file and the
+/// `PrivateKeyFile=` path always name the same file.
+fn networkd_key_path(prefix: &str, ifname: &str, options: &ExportOptions) -> Result<PathBuf> {
+    Ok(match &options.private_key_file {
+        Some(path) => {
+            let file_name = path
+                .file_name()
+                .with_context(|| format!("Private key file {:?} has no file name", path))?;
+            path.with_file_name(format!("{}{}", prefix, file_name.to_string_lossy()))
+        }
+        None => Path::new(NETWORKD_DIR).join(format!("{}{}.key", prefix, ifname)),
+    })
+}
+
+/// systemd-networkd unit files, numbered so they sort before distro defaults,
+/// and the private key file the netdev references
+fn networkd_files(
+    prefix: &str,
+    ifname: &str,
+    units: crate::networkd::NetworkdUnits,
+    key_path: &Path,
+    private_key: &impl std::fmt::Display,
+) -> Result<Vec<RenderedFile>> {
+    let key_name = key_path
+        .file_name()
+        .with_context(|| format!("Private key file {:?} has no file name", key_path))?;
+    Ok(vec![
+        RenderedFile {
+            name: format!("{}25-{}.netdev", prefix, ifname),
+            contents: units.netdev,
+        },
+        RenderedFile {
+            name: format!("{}25-{}.network", prefix, ifname),
+            contents: units.network,
+        },
+        RenderedFile {
+            name: key_name.to_string_lossy().into_owned(),
+            contents: format!("{}\n", private_key),
+        },
+    ])
+}
+
+/// Turns the server/client model into config files.
+///
+/// Built-in formats implement this via [`Format`]; user templates via
+/// [`crate::template::TemplateRenderer`].
+pub trait Renderer {
+    /// Render a client's config files
+    fn render_client(&self, client: &ClientConfig, options: &ExportOptions)
+    -> Result<Vec<RenderedFile>>;
+
+    /// Render the server's config files
+    fn render_server(&self, server: &ServerConfig, options: &ExportOptions)
+    -> Result<Vec<RenderedFile>>;
+
+    /// Names of the files `render_client` writes for a client
+    fn client_file_names(&self, client: &ClientConfig, options: &ExportOptions) -> Result<Vec<String>> {
+        Ok(self.render_client(client, options)?.into_iter().map(|file| file.name).collect())
+    }
+}
+
+impl Renderer for Format {
+    fn render_client(
+        &self,
+        client: &ClientConfig,
+        options: &ExportOptions,
+    ) -> Result<Vec<RenderedFile>> {
+        render_client(client, *self, options)
+    }
+
+    /// The wg-quick file is rendered fresh; callers merge it with any existing
+    /// `wg0.conf` to keep hand-written sections.
+    fn render_server(
+        &self,
+        server: &ServerConfig,
+        options: &ExportOptions,
+    ) -> Result<Vec<RenderedFile>> {
+        render_server(server, *self, options)
+    }
+
+    /// Built-in formats name their files without rendering them
+    fn client_file_names(&self, client: &ClientConfig, options: &ExportOptions) -> Result<Vec<String>> {
+        client_file_names(client, *self, options)
+    }
+}
+
+/// Names of the files `render_client` writes for a client in a built-in format
+fn client_file_names(client: &ClientConfig, format: Format, options: &ExportOptions) -> Result<Vec<String>> {
+    Ok(match format {
+        Format::WgQuick => vec![format!("{}.conf", client.name)],
+        Format::Nm => vec![format!("{}.nmconnection", client.name)],
+        Format::Networkd => {
+            let prefix = format!("{}-", client.name);
+            let key_path = networkd_key_path(&prefix, INTERFACE, options)?;
+            let key_name = key_path
+                .file_name()
+                .with_context(|| format!("Private key file {:?} has no file name", key_path))?;
+            vec![
+                format!("{}25-{}.netdev", prefix, INTERFACE),
+                format!("{}25-{}.network", prefix, INTERFACE),
+                key_name.to_string_lossy().into_owned(),
+            ]
+        }
+        Format::Uci => vec![format!("{}.uci", client.name)],
+        Format::Routeros => vec![format!("{}.rsc", client.name)],
+        Format::Kubernetes => vec![format!("{}.secret.yaml", client.name)],
+        Format::Compose => vec![format!("{}.compose.yaml", client.name)],
+    })
+}
+
+/// Render a client config in a built-in format
+fn render_client(
+    client: &ClientConfig,
+    format: Format,
+    options: &ExportOptions,
+) -> Result<Vec<RenderedFile>> {
+    Ok(match format {
+        Format::WgQuick => vec![RenderedFile {
+            name: format!("{}.conf", client.name),
+            contents: client.to_wireguard_config()?,
+        }],
+        Format::Nm => vec![RenderedFile {
+            name: format!("{}.nmconnection", client.name),
+            contents: networkmanager_keyfile(client),
+        }],
+        Format::Networkd => {
+            let prefix = format!("{}-", client.name);
+            let key_path = networkd_key_path(&prefix, INTERFACE, options)?;
+            let units = client.to_networkd_config(INTERFACE, &key_path)?;
+            networkd_files(&prefix, INTERFACE, units, &key_path, &client.keys.private)?
+        }
+        Format::Uci => vec![RenderedFile {
+            name: format!("{}.uci", client.name),
+            contents: router::uci_batch(client),
+        }],
+        Format::Routeros => vec![RenderedFile {
+            name: format!("{}.rsc", client.name),
+            contents: router::routeros_script(client),
+        }],
+        Format::Kubernetes | Format::Compose => vec![container_file(
+            &client.name,
+            INTERFACE,
+            client_container_name(&client.name),
+            format,
+            &client.to_wireguard_config()?,
+            None,
+            options,
+        )?],
+    })
+}
+
+/// The container and Secret name for client `name`, `wg-<name>` made a
+/// valid DNS-1123 label
+pub fn client_container_name(name: &str) -> String {
+    container::dns_label(&format!("wg-{}", name))
+}
+
+/// Render the server config in a built-in format
+fn render_server(
+    server: &ServerConfig,
+    format: Format,
+    options: &ExportOptions,
+) -> Result<Vec<RenderedFile>> {
+    Ok(match format {
+        Format::WgQuick => vec![RenderedFile {
+            name: format!("{}.conf", server.wg_interface),
+            contents: server.to_wireguard_config()?,
+        }],
+        Format::Nm | Format::Uci | Format::Routeros => {
+            anyhow::bail!("The {} format is only available for clients", format)
+        }
+        Format::Networkd => {
+            let key_path = networkd_key_path("", &server.wg_interface, options)?;
+            let units = server.to_networkd_config(&server.wg_interface, &key_path)?;
+            networkd_files("", &server.wg_interface, units, &key_path, &server.keys.private)?
+        }
+        Format::Kubernetes | Format::Compose => vec![container_file(
+            &server.wg_interface,
+            &server.wg_interface,
+            container::dns_label(&server.wg_interface),
+            format,
+            &server.to_wireguard_config()?,
+            Some(server.port),
+            options,
+        )?],
+    })
+}
+
+/// Linux interface name for a client: at most 15 characters from a safe set
+pub fn interface_name(prefix: &str, name: &str) -> String {
+    let mut ifname = prefix.to_string();
+    ifname.extend(
+        name.chars()
+            .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')),
+    );
+    ifname.truncate(15);
+    ifname
+}
+
+/// Render a NetworkManager keyfile for a client.
+///
+/// Split tunnels set `never-default` so only the VPN network is routed;
+/// full tunnels take the default route and prefer the VPN's DNS servers.
+fn networkmanager_keyfile(client: &ClientConfig) -> String {
+    let full_tunnel = client.is_full_tunnel();
+    let allowed_ips: String = client
+        .allowed_ip_list()
+        .iter()
+        .map(|ip| format!("{};", ip))
+        .collect();
+    let dns: String = client.dns_servers().iter().map(|s| format!("{};", s)).collect();
+    let mut dns_search: String = client.dns_search().iter().map(|s| format!("{};", s)).collect();
+    let ipv6_default = client.allowed_ip_list().contains(&"::/0");
+
+    let mut keyfile = format!(
+        "[connection]\n\
+         id={name}\n\
+         type=wireguard\n\
+         interface-name={ifname}\n\
+         \n\
+         [wireguard]\n\
+         private-key={private_key}\n\
+         \n\
+         [wireguard-peer.{public_key}]\n\
+         endpoint={endpoint}:{port}\n\
+         persistent-keepalive={keepalive}\n\
+         allowed-ips={allowed_ips}\n\
+         \n\
+         [ipv4]\n\
+         method=manual\n\
+         address1={ip}/32\n\
+         dns={dns}\n",
+        name = client.name,
+        ifname = interface_name("wg-", &client.name),
+        private_key = client.keys.private,
+        public_key = client.server_public_key,
+        endpoint = client.server_endpoint,
+        port = client.server_port,
+        allowed_ips = allowed_ips,
+        ip = client.ip,
+        dns = dns,
+        keepalive = client.keepalive(),
+    );
+    if full_tunnel {
+        // `~` routes lookups for every domain to this connection
+        dns_search.push_str("~;");
+        keyfile.push_str("dns-priority=-50\n");
+    } else {
+        keyfile.push_str("never-default=true\n");
+    }
+    if !dns_search.is_empty() {
+        keyfile.push_str(&format!("dns-search={}\n", dns_search));
+    }
+
+    keyfile.push_str("\n[ipv6]\n");
+    if ipv6_default {
+        keyfile.push_str("method=ignore\n");
+    } else {
+        keyfile.push_str("method=disabled\n");
+    }
+
+    keyfile
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_support;
+
+    fn client(allowed_ips: &str) -> ClientConfig {
+        test_support::client(&mut test_support::server(), "laptop", allowed_ips)
+    }
+
+    #[test]
+    fn test_networkmanager_split_tunnel() {
+        let client = client("10.0.0.0/24");
+        let files = Format::Nm.render_client(&client, &ExportOptions::default()).unwrap();
+        assert_eq!(files[0].name, "laptop.nmconnection");
+
+        let keyfile = &files[0].contents;
+        assert!(keyfile.contains("type=wireguard\n"));
+        assert!(keyfile.contains("interface-name=wg-laptop\n"));
+        assert!(keyfile.contains(&format!("[wireguard-peer.{}]\n", client.server_public_key)));
+        assert!(keyfile.contains("allowed-ips=10.0.0.0/24;\n"));
+        assert!(keyfile.contains("address1=10.0.0.2/32\n"));
+        assert!(keyfile.contains("never-default=true\n"));
+        assert!(keyfile.contains("[ipv6]\nmethod=disabled\n"));
+    }
+
+    #[test]
+    fn test_networkmanager_full_tunnel() {
+        let keyfile = &Format::Nm.render_client(&client("0.0.0.0/0"), &ExportOptions::default()).unwrap()[0].contents;
+        assert!(keyfile.contains("allowed-ips=0.0.0.0/0;\n"));
+        assert!(keyfile.contains("dns-search=~;\n"));
+        assert!(!keyfile.contains("never-default"));
+    }
+
+    #[test]
+    fn test_networkmanager_search_domains() {
+        let mut client = client("10.0.0.0/24");
+        client.dns = Some(vec!["10.0.0.1".to_string(), "corp.example".to_string()]);
+        let keyfile = &Format::Nm.render_client(&client, &ExportOptions::default()).unwrap()[0].contents;
+        assert!(keyfile.contains("dns=10.0.0.1;\n"));
+        assert!(keyfile.contains("dns-search=corp.example;\n"));
+
+        client.allowed_ips = "0.0.0.0/0".to_string();
+        let keyfile = &Format::Nm.render_client(&client, &ExportOptions::default()).unwrap()[0].contents;
+        assert!(keyfile.contains("dns-search=corp.example;~;\n"));
+    }
+
+    #[test]
+    fn test_client_file_names_match_rendered_files() {
+        let client = client("10.0.0.0/24");
+        let options = ExportOptions::default();
+        for format in Format::value_variants() {
+            let rendered: Vec<String> =
+                format.render_client(&client, &options).unwrap().into_iter().map(|f| f.name).collect();
+            assert_eq!(format.client_file_names(&client, &options).unwrap(), rendered, "{}", format);
+        }
+    }
+
+    #[test]
+    fn test_networkd_file_names() {
+        let client = client("10.0.0.0/24");
+        let files = Format::Networkd.render_client(&client, &ExportOptions::default()).unwrap();
+        let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["laptop-25-wg0.netdev", "laptop-25-wg0.network", "laptop-wg0.key"]);
+        assert!(files[0].contents.contains("PrivateKeyFile=/etc/systemd/network/laptop-wg0.key\n"));
+        assert_eq!(files[2].contents, format!("{}\n", client.keys.private));
+
+        // A given key path gets the same prefix as the file written for it
+        let options = ExportOptions {
+            private_key_file: Some(PathBuf::from("/etc/wireguard/private.key")),
+            ..Default::default()
+        };
+        let files = Format::Networkd.render_client(&client, &options).unwrap();
+        assert!(files[0].contents.contains("PrivateKeyFile=/etc/wireguard/laptop-private.key\n"));
+        assert_eq!(files[2].name, "laptop-private.key");
+    }
+
+    #[test]
+    fn test_interface_name_is_truncated_and_sanitized() {
+        assert_eq!(interface_name("wg-", "my laptop/2"), "wg-mylaptop2");
+        assert_eq!(interface_name("wg-", "a-very-long-client-name"), "wg-a-very-long-");
+    }
+
+    #[test]
+    fn test_server_formats() {
+        let server = test_support::server();
+        for format in Format::value_variants() {
+            let rendered = format.render_server(&server, &ExportOptions::default()).is_ok();
+            assert_eq!(rendered, format.is_server_format(), "{}", format);
+        }
+    }
+
+    #[test]
+    fn test_container_formats_accept_any_client_name() {
+        let client = test_support::client(&mut test_support::server(), "Laptop_2.home", "10.0.0.0/24");
+        let options = ExportOptions::default();
+        let secret = &Format::Kubernetes.render_client(&client, &options).unwrap()[0];
+        assert_eq!(secret.name, "Laptop_2.home.secret.yaml");
+        assert!(secret.contents.contains("name: \"wg-laptop-2-home\"\n"));
+        let compose = &Format::Compose.render_client(&client, &options).unwrap()[0];
+        assert!(compose.contents.contains("container_name: \"wg-laptop-2-home\"\n"));
+    }
+}
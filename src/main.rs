@@ -1,109 +1,266 @@
 use anyhow::{Context, Result};
 use clap::Parser;
-use cli::{Cli, Commands};
+use cli::{
+    Cli, ClientCommands, Commands, ContextCommands, ExportArgs, KeyCommands, PoolCommands, RenderArgs, ServerCommands,
+};
+use sha2::{Digest, Sha256};
 use std::fs;
-use std::net::IpAddr;
-use std::os::unix::fs::PermissionsExt;
-use std::path::PathBuf;
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
+mod batch;
+mod bundle;
+mod changes;
 mod cli;
 mod config;
+mod container;
+mod context;
 mod crypto;
+#[cfg(feature = "age")]
+mod encrypt;
+mod export;
+mod managed;
+mod networkd;
+mod pool;
+#[cfg(feature = "qr")]
+mod qr;
+mod router;
+mod routes;
+mod settings;
+mod sync;
+mod template;
 
-use config::{ClientConfig, ServerConfig, WireGuardConfig};
+use batch::TunnelMode;
+use bundle::BundleFormat;
+use changes::{ChangeSet, Drift};
+use config::{ClientConfig, Firewall, ServerConfig};
+#[cfg(feature = "qr")]
+use config::WireGuardConfig;
+use export::{ExportOptions, Format, RenderedFile, Renderer};
+use settings::Settings;
+use template::TemplateRenderer;
+use crypto::{PresharedKey, PrivateKey, PublicKey};
 
 fn main() -> Result<()> {
     // Parse CLI arguments
     let cli = Cli::parse();
+    let settings = cli.settings()?;
+    let home = settings.output.clone().unwrap_or_else(|| PathBuf::from("."));
+    let context_name = context::resolve(&home, settings.server.as_deref())?;
+    let output = context::dir(&home, &context_name);
     match cli.command {
         Commands::Init {
             endpoint,
             port,
             network,
+            server_ip,
             interface,
-        } => init_server(
-            &cli.output,
-            &endpoint,
-            port,
-            &network,
-            &interface,
-            cli.verbose,
-        ),
+            format,
+            firewall,
+            wg_interface,
+            template,
+        } => {
+            let others: Vec<(String, ServerConfig)> = load_contexts(&home)?
+                .into_iter()
+                .filter(|(other, _)| *other != context_name)
+                .collect();
+            let wg_interface = match wg_interface {
+                Some(name) => name,
+                None => next_wg_interface(&others),
+            };
+            check_other_contexts(&others, port, &network, &wg_interface)?;
+            check_host_routes(&network, &wg_interface)?;
+            init_server(
+                &output,
+                &endpoint,
+                port,
+                &network,
+                server_ip,
+                &interface,
+                wg_interface,
+                format.or(settings.server_format()).unwrap_or_default(),
+                firewall.or(settings.firewall).unwrap_or_default(),
+                template,
+                cli.verbose,
+                cli.dry_run,
+            )
+        }
         Commands::Client {
+            command: Some(command),
+            ..
+        } => client_command(&home, &output, *command, &settings, cli.verbose, cli.dry_run),
+        Commands::Client {
+            command: None,
             name,
             ip,
+            pool,
             full_tunnel,
+            split_tunnel,
+            dns,
+            keepalive,
+            vanity,
+            owner,
+            tags,
+            format,
+            template,
             #[cfg(feature = "qr")]
             qr,
+            #[cfg(feature = "qr")]
+            qr_out,
+            #[cfg(feature = "age")]
+            encrypt_to,
         } => add_client(
-            &cli.output,
-            &name,
+            &output,
+            &name.context("Client name is required")?,
             ip,
-            full_tunnel,
+            pool.as_deref(),
+            full_tunnel || (!split_tunnel && settings.tunnel == Some(TunnelMode::Full)),
+            if dns.is_empty() { settings.dns.clone() } else { Some(dns) },
+            keepalive.or(settings.keepalive),
+            vanity.as_deref(),
+            owner,
+            tags,
+            format.or(settings.format).unwrap_or_default(),
+            template,
             #[cfg(feature = "qr")]
             qr,
+            #[cfg(feature = "qr")]
+            qr_out.as_deref(),
+            #[cfg(feature = "age")]
+            encrypt_to,
             cli.verbose,
+            cli.dry_run,
         ),
-        Commands::List => list_clients(&cli.output),
-        Commands::Revoke { name } => revoke_client(&cli.output, &name, cli.verbose),
-        Commands::Show => show_server(&cli.output),
+        Commands::List { all: false } => list_clients(&output),
+        Commands::List { all: true } => list_all_clients(&home),
+        Commands::Revoke { name, tags, from } => {
+            revoke_clients(&output, name, &tags, from.as_deref(), cli.verbose, cli.dry_run)
+        }
+        Commands::Show => show_server(&output),
+        Commands::Check => check_files(&output, cli.verbose),
+        Commands::Lint => lint_state(&output),
+        Commands::Render { adopt } => render_files(&output, adopt, cli.verbose, cli.dry_run),
+        Commands::Sync { file } => sync_state(&output, &file, cli.verbose, cli.dry_run),
+        Commands::Export {
+            name,
+            render,
+            options,
+            stdout,
+            #[cfg(feature = "qr")]
+            qr_out,
+        } => export_config(
+            &output,
+            name.as_deref(),
+            &render,
+            &export_options(options)?,
+            stdout,
+            #[cfg(feature = "qr")]
+            qr_out.as_deref(),
+            cli.verbose,
+            cli.dry_run,
+        ),
+        #[cfg(feature = "qr")]
+        Commands::Qr {
+            name,
+            file,
+            size,
+            ec_level,
+        } => write_qr(
+            &output,
+            &name,
+            file,
+            qr::QrOptions { size, ec_level },
+            cli.dry_run,
+        ),
+        Commands::Bundle {
+            name,
+            file,
+            format,
+            include,
+            password_file,
+        } => write_bundle(
+            &output,
+            &name,
+            file,
+            format,
+            &include,
+            password_file.as_deref(),
+            cli.dry_run,
+        ),
+        Commands::Server { command } => server_command(&output, command, cli.verbose, cli.dry_run),
+        Commands::Pool { command } => pool_command(&output, command, cli.dry_run),
+        Commands::Context { command } => context_command(&home, &context_name, command),
+        Commands::Annotate => annotate(&output),
+        Commands::Key { command } => key_command(command),
     }
 }
 
 /// Initialize a new WireGuard server
+#[allow(clippy::too_many_arguments)]
 fn init_server(
-    output_dir: &PathBuf,
+    output_dir: &Path,
     endpoint: &str,
     port: u16,
     network: &str,
+    server_ip: Option<Ipv4Addr>,
     interface: &str,
+    wg_interface: String,
+    format: Format,
+    firewall: Firewall,
+    template: Option<String>,
     verbose: bool,
+    dry_run: bool,
 ) -> Result<()> {
     // Check if server is already initialized
-    let config_path = output_dir.join("wg-server.json");
+    let config_path = output_dir.join(context::STATE_FILE);
     if config_path.exists() {
         anyhow::bail!("Server already initialized. Use 'rusty-wire show' to view configuration.");
     }
 
     // Generate server keypair and configuration
     let server_keys = crypto::generate_keypair()?;
-    let server_config = ServerConfig::new(
+    let mut server_config = ServerConfig::new(
         endpoint.to_string(),
         port,
         network.to_string(),
         interface.to_string(),
         server_keys,
     )?;
-
-    // Save server config as JSON for state management
-    let json = serde_json::to_string_pretty(&server_config)?;
-    fs::write(&config_path, json)
-        .with_context(|| format!("Failed to write server config to {:?}", config_path))?;
-
-    // Set wg-server.json file permissions
-    let mut permissions = fs::metadata(&config_path)?.permissions();
-    if (permissions.mode() & 0o777) != 0o600 {
-        permissions.set_mode(0o600);
-        fs::set_permissions(&config_path, permissions)?;
+    server_config.format = format;
+    server_config.template = template;
+    server_config.firewall = firewall;
+    server_config.wg_interface = wg_interface;
+    if let Some(ip) = server_ip {
+        server_config.set_server_ip(ip)?;
     }
 
-    // Generate the actual WireGuard config file
-    let wg_config = server_config.to_wireguard_config()?;
-    let wg_config_path = output_dir.join("wg0.conf");
-    fs::write(&wg_config_path, wg_config)
-        .with_context(|| format!("Failed to write WireGuard config to {:?}", wg_config_path))?;
-
-    // Set wg0.conf file permissions
-    let mut permissions = fs::metadata(&wg_config_path)?.permissions();
-    if (permissions.mode() & 0o777) != 0o600 {
-        permissions.set_mode(0o600);
-        fs::set_permissions(&wg_config_path, permissions)?;
+    // Save server state and WireGuard config
+    let mut changes = ChangeSet::new();
+    changes.note(format!(
+        "+ server {}:{} network {} address {}",
+        endpoint,
+        port,
+        server_config.network,
+        server_config.server_ip()?
+    ));
+    let wg_config_path = stage_server(&mut changes, output_dir, &server_config)?;
+    if !dry_run {
+        fs::create_dir_all(output_dir)
+            .with_context(|| format!("Failed to create {:?}", output_dir))?;
+    }
+    if !finish(&changes, dry_run)? {
+        return Ok(());
     }
+
     if verbose {
         println!("Server initialized:");
         println!("  Endpoint: {}:{}", endpoint, port);
-        println!("  Network: {}", network);
+        println!("  Network: {}", server_config.network);
+        println!("  Server IP: {}", server_config.server_ip()?);
         println!("  Interface: {}", interface);
+        println!("  WireGuard Interface: {}", server_config.wg_interface);
         println!("  Config: {:?}", wg_config_path);
     } else {
         println!("✓ Server initialized at {:?}", wg_config_path);
@@ -113,21 +270,28 @@ fn init_server(
 }
 
 /// Add a new client
+#[allow(clippy::too_many_arguments)]
 fn add_client(
-    output_dir: &PathBuf,
+    output_dir: &Path,
     name: &str,
     custom_ip: Option<IpAddr>,
+    pool: Option<&str>,
     full_tunnel: bool,
+    dns: Option<Vec<String>>,
+    keepalive: Option<u16>,
+    vanity: Option<&str>,
+    owner: Option<String>,
+    tags: Vec<String>,
+    format: Format,
+    template: Option<String>,
     #[cfg(feature = "qr")] qr: bool,
+    #[cfg(feature = "qr")] qr_out: Option<&Path>,
+    #[cfg(feature = "age")] encrypt_to: Vec<String>,
     verbose: bool,
+    dry_run: bool,
 ) -> Result<()> {
     // Load server config
-    let config_path = output_dir.join("wg-server.json");
-    if !config_path.exists() {
-        anyhow::bail!("No server configuration found. Run 'rusty-wire init' first.");
-    }
-    let config_data = fs::read_to_string(&config_path)?;
-    let mut server_config: ServerConfig = serde_json::from_str(&config_data)?;
+    let mut server_config = load_server(output_dir)?;
 
     // Check if client already exists
     if server_config.clients.iter().any(|c| c.name == name) {
@@ -135,48 +299,37 @@ fn add_client(
     }
 
     // Generate client keypair
-    let client_keys = crypto::generate_keypair()?;
-    let client_ip = custom_ip.unwrap_or_else(|| server_config.next_client_ip());
-
-    // Generate client config
-    let allowed_ips = if full_tunnel {
-        "0.0.0.0/0".to_string()
-    } else {
-        server_config.network.clone()
+    let client_keys = match vanity {
+        Some(prefix) => find_vanity_keypair(prefix)?,
+        None => crypto::generate_keypair()?,
     };
-
-    let client_config = ClientConfig::new(
-        name.to_string(),
-        client_ip,
-        client_keys,
-        server_config.endpoint.clone(),
-        server_config.port,
-        server_config.keys.public.clone(),
-        allowed_ips,
-    );
+    let mut client_config = server_config.new_client(name, custom_ip, pool, full_tunnel, client_keys)?;
+    let client_ip = client_config.ip;
+    client_config.owner = owner;
+    client_config.tags = tags;
+    client_config.dns = dns;
+    client_config.keepalive = keepalive;
+    client_config.format = format;
+    client_config.template = template;
+    #[cfg(feature = "age")]
+    {
+        client_config.encrypt_to = encrypt_to;
+    }
 
     // Add client to server config
     server_config.add_client(&client_config)?;
 
-    // Save updated server config
-    let json = serde_json::to_string_pretty(&server_config)?;
-    fs::write(&config_path, json)?;
-
-    // Regenerate server WireGuard config
-    let wg_config = server_config.to_wireguard_config()?;
-    fs::write(output_dir.join("wg0.conf"), wg_config)?;
-
-    // Generate client config file
-    let client_wg_config = client_config.to_wireguard_config()?;
-    let client_config_path = output_dir.join(format!("{}.conf", name));
-    fs::write(&client_config_path, &client_wg_config)?;
-
-    // Set client.conf file permissions
-    let mut permissions = fs::metadata(&client_wg_config)?.permissions();
-    if (permissions.mode() & 0o777) != 0o600 {
-        permissions.set_mode(0o600);
-        fs::set_permissions(&client_wg_config, permissions)?;
+    // Stage updated server state and client config
+    let mut changes = ChangeSet::new();
+    changes.note(format!("+ peer '{}' {}", name, client_ip));
+    stage_server(&mut changes, output_dir, &server_config)?;
+    let client_config_path = stage_client(&mut changes, output_dir, &client_config)?;
+    #[cfg(feature = "qr")]
+    let qr_path = stage_qr(&mut changes, output_dir, &client_config, qr_out)?;
+    if !finish(&changes, dry_run)? {
+        return Ok(());
     }
+
     if verbose {
         println!("Client '{}' added:", name);
         println!("  IP: {}", client_ip);
@@ -191,23 +344,39 @@ fn add_client(
     #[cfg(feature = "qr")]
     if qr {
         println!("\nQR Code for mobile import:");
-        if let Err(e) = qr2term::print_qr(&client_wg_config) {
+        if let Err(e) = qr2term::print_qr(client_config.to_wireguard_config()?) {
             eprintln!("Failed to generate QR code: {}", e);
         }
     }
+    #[cfg(feature = "qr")]
+    if let Some(path) = qr_path {
+        println!("✓ QR code written to {:?}", path);
+    }
 
     Ok(())
 }
 
+/// Stage a client's wg-quick config as a QR image at `qr_out`, relative to
+/// the output directory, returning where it goes
+#[cfg(feature = "qr")]
+fn stage_qr(
+    changes: &mut ChangeSet,
+    output_dir: &Path,
+    client: &ClientConfig,
+    qr_out: Option<&Path>,
+) -> Result<Option<PathBuf>> {
+    let Some(path) = qr_out.map(|path| output_dir.join(path)) else {
+        return Ok(None);
+    };
+    let image = qr::image(&path, &client.to_wireguard_config()?, qr::QrOptions::default())?;
+    changes.write_bytes(&path, image);
+    Ok(Some(path))
+}
+
 /// List configured clients
-fn list_clients(output_dir: &PathBuf) -> Result<()> {
+fn list_clients(output_dir: &Path) -> Result<()> {
     // Load server config
-    let config_path = output_dir.join("wg-server.json");
-    if !config_path.exists() {
-        anyhow::bail!("No server configuration found. Run 'rusty-wire init' first.");
-    }
-    let config_data = fs::read_to_string(&config_path)?;
-    let server_config: ServerConfig = serde_json::from_str(&config_data)?;
+    let server_config = load_server(output_dir)?;
     if server_config.clients.is_empty() {
         println!("No clients configured.");
         return Ok(());
@@ -222,52 +391,1175 @@ fn list_clients(output_dir: &PathBuf) -> Result<()> {
     Ok(())
 }
 
-/// Revoke a client and remove their configuration
-fn revoke_client(output_dir: &PathBuf, name: &str, verbose: bool) -> Result<()> {
-    // Load server config
-    let config_path = output_dir.join("wg-server.json");
-    if !config_path.exists() {
+/// List the clients of every context in the home directory
+fn list_all_clients(home: &Path) -> Result<()> {
+    let contexts = load_contexts(home)?;
+    if contexts.is_empty() {
         anyhow::bail!("No server configuration found. Run 'rusty-wire init' first.");
     }
-    let config_data = fs::read_to_string(&config_path)?;
-    let mut server_config: ServerConfig = serde_json::from_str(&config_data)?;
-
-    // Remove client from server config if found
-    if server_config.remove_client(name)? {
-        // Save updated server config
-        let json = serde_json::to_string_pretty(&server_config)?;
-        fs::write(&config_path, json)?;
-
-        // Regenerate server WireGuard config
-        let wg_config = server_config.to_wireguard_config()?;
-        fs::write(output_dir.join("wg0.conf"), wg_config)?;
-
-        // Remove client config file
-        let client_config_path = output_dir.join(format!("{}.conf", name));
-        if client_config_path.exists() {
-            fs::remove_file(&client_config_path)?;
-            if verbose {
-                println!("Removed client config: {:?}", client_config_path);
+
+    for (i, (name, server_config)) in contexts.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        println!(
+            "{} ({}, {}):",
+            name, server_config.wg_interface, server_config.network
+        );
+        if server_config.clients.is_empty() {
+            println!("  No clients configured.");
+        }
+        for client in &server_config.clients {
+            println!("  {} - {}", client.name, client.ip);
+        }
+    }
+
+    Ok(())
+}
+
+/// Add, remove or list address pools and reserved ranges
+fn pool_command(output_dir: &Path, command: PoolCommands, dry_run: bool) -> Result<()> {
+    let mut server_config = load_server(output_dir)?;
+    let (note, message) = match command {
+        PoolCommands::List => return list_pools(&server_config),
+        PoolCommands::Add { name, network } => {
+            server_config.add_pool(&name, &network)?;
+            for (_, range) in server_config.reserved_in_pools()?.iter().filter(|(pool, _)| pool.name == name) {
+                eprintln!("Warning: pool '{}' overlaps reserved range {}, which it will not hand out", name, range);
+            }
+            (
+                format!("+ pool '{}' {}", name, network),
+                format!("Pool '{}' added for {}", name, network),
+            )
+        }
+        PoolCommands::Remove { name } => {
+            if !server_config.remove_pool(&name) {
+                anyhow::bail!("Pool '{}' not found", name);
+            }
+            (format!("- pool '{}'", name), format!("Pool '{}' removed", name))
+        }
+        PoolCommands::Reserve { range } => {
+            server_config.reserve(&range)?;
+            for (pool, _) in server_config.reserved_in_pools()?.iter().filter(|(_, r)| *r == range.trim()) {
+                eprintln!(
+                    "Warning: range {} overlaps pool '{}' ({}), which will not hand it out",
+                    range, pool.name, pool.network
+                );
+            }
+            (format!("+ reserved {}", range), format!("Reserved {}", range))
+        }
+        PoolCommands::Unreserve { range } => {
+            if !server_config.unreserve(&range)? {
+                anyhow::bail!("Range {} is not reserved", range);
+            }
+            (format!("- reserved {}", range), format!("Released {}", range))
+        }
+    };
+
+    let mut changes = ChangeSet::new();
+    changes.note(note);
+    stage_server(&mut changes, output_dir, &server_config)?;
+    if !finish(&changes, dry_run)? {
+        return Ok(());
+    }
+    println!("✓ {}", message);
+
+    Ok(())
+}
+
+/// Print pools and reserved ranges with the clients in each
+fn list_pools(server_config: &ServerConfig) -> Result<()> {
+    if server_config.pools.is_empty() && server_config.reserved.is_empty() {
+        println!("No pools or reserved ranges; clients get IPs from {}.", server_config.network);
+        return Ok(());
+    }
+    let clients_in = |range: pool::AddressRange| {
+        server_config
+            .clients
+            .iter()
+            .filter(|c| matches!(c.ip, IpAddr::V4(ip) if range.contains(ip)))
+            .count()
+    };
+
+    if !server_config.pools.is_empty() {
+        println!("Pools:");
+        for pool in &server_config.pools {
+            let count = clients_in(pool.range()?);
+            println!("  {:<12} {:<18} {} client(s)", pool.name, pool.network, count);
+        }
+    }
+    if !server_config.reserved.is_empty() {
+        println!("Reserved:");
+        for range in &server_config.reserved {
+            let count = clients_in(pool::AddressRange::parse(range)?);
+            println!("  {:<31} {} client(s)", range, count);
+        }
+    }
+
+    Ok(())
+}
+
+/// List, switch or print server contexts
+fn context_command(home: &Path, current: &str, command: ContextCommands) -> Result<()> {
+    match command {
+        ContextCommands::List => {
+            let contexts = load_contexts(home)?;
+            if contexts.is_empty() {
+                println!("No contexts initialized. Run 'rusty-wire init' first.");
+            }
+            for (name, server_config) in contexts {
+                println!(
+                    "{} {:<12} {:<6} {}:{}  {}  {} client(s)",
+                    if name == current { '*' } else { ' ' },
+                    name,
+                    server_config.wg_interface,
+                    server_config.endpoint,
+                    server_config.port,
+                    server_config.network,
+                    server_config.clients.len()
+                );
+            }
+        }
+        ContextCommands::Use { name } => {
+            context::validate_name(&name)?;
+            if !context::exists(home, &name) {
+                anyhow::bail!(
+                    "Context '{}' has no server. Run 'rusty-wire --server {} init' first.",
+                    name,
+                    name
+                );
             }
+            context::set_current(home, &name)?;
+            println!("✓ Now using context '{}'", name);
         }
+        ContextCommands::Current => println!("{}", current),
+    }
+
+    Ok(())
+}
 
+/// Every context in the home directory with its server, skipping unreadable ones with a warning
+fn load_contexts(home: &Path) -> Result<Vec<(String, ServerConfig)>> {
+    let mut contexts = Vec::new();
+    for name in context::list(home)? {
+        match load_server(&context::dir(home, &name)) {
+            Ok(server) => contexts.push((name, server)),
+            Err(e) => eprintln!("Warning: skipping context '{}': {:#}", name, e),
+        }
+    }
+    Ok(contexts)
+}
+
+/// The first `wgN` interface name not used by the `others` contexts
+fn next_wg_interface(others: &[(String, ServerConfig)]) -> String {
+    (0..)
+        .map(|n| format!("wg{}", n))
+        .find(|ifname| !others.iter().any(|(_, server)| server.wg_interface == *ifname))
+        .expect("unbounded range")
+}
+
+/// Refuse an interface name one of the `others` contexts has, and warn about shared ports and networks
+fn check_other_contexts(others: &[(String, ServerConfig)], port: u16, network: &str, wg_interface: &str) -> Result<()> {
+    let network = config::normalize_network(network)?;
+    config::validate_interface(wg_interface)?;
+    for (other, server) in others {
+        if server.wg_interface == wg_interface {
+            anyhow::bail!("Interface {} is already used by context '{}'", wg_interface, other);
+        }
+        if server.port == port {
+            eprintln!("Warning: port {} is also used by context '{}'", port, other);
+        }
+        if server.network == network {
+            eprintln!("Warning: network {} is also used by context '{}'", network, other);
+        }
+    }
+    Ok(())
+}
+
+/// Warn when the VPN network overlaps a route on this host, such as the LAN's
+fn check_host_routes(network: &str, wg_interface: &str) -> Result<()> {
+    let network = routes::Prefix::parse(network)?;
+    let host_routes = routes::host_routes()?;
+    for route in routes::conflicts(&network, &host_routes, wg_interface) {
+        eprintln!(
+            "Warning: network {} overlaps route {} on {}",
+            network, route.prefix, route.interface
+        );
+    }
+    Ok(())
+}
+
+/// Revoke clients selected by name, tag or list file.
+///
+/// Nothing is changed unless every named client exists.
+fn revoke_clients(
+    output_dir: &Path,
+    name: Option<String>,
+    tags: &[String],
+    from: Option<&Path>,
+    verbose: bool,
+    dry_run: bool,
+) -> Result<()> {
+    // Load server config
+    let mut server_config = load_server(output_dir)?;
+
+    // Collect the selected names, keeping their order
+    let mut names: Vec<String> = name.into_iter().collect();
+    if let Some(path) = from {
+        let contents =
+            fs::read_to_string(path).with_context(|| format!("Failed to read {:?}", path))?;
+        names.extend(batch::parse_name_list(&contents));
+    }
+    let missing: Vec<&String> = names
+        .iter()
+        .filter(|name| !server_config.clients.iter().any(|c| &c.name == *name))
+        .collect();
+    if !missing.is_empty() {
+        for name in &missing {
+            println!("  ✗ '{}': not found", name);
+        }
+        anyhow::bail!("{} client(s) not found; no clients were revoked", missing.len());
+    }
+    for tag in tags {
+        let tagged: Vec<String> = server_config
+            .clients
+            .iter()
+            .filter(|c| c.tags.contains(tag))
+            .map(|c| c.name.clone())
+            .collect();
+        if tagged.is_empty() {
+            anyhow::bail!("No clients are tagged '{}'", tag);
+        }
+        names.extend(tagged);
+    }
+    let mut seen = std::collections::HashSet::new();
+    names.retain(|name| seen.insert(name.clone()));
+
+    // Remove clients from server config and stage removal of their files
+    let mut changes = ChangeSet::new();
+    let mut removed = Vec::new();
+    for name in &names {
+        let client = match server_config.clients.iter().find(|c| &c.name == name) {
+            Some(client) => client.clone(),
+            None => anyhow::bail!("Client '{}' not found", name),
+        };
+        server_config.remove_client(name)?;
+        changes.note(format!("- peer '{}' {}", name, client.ip));
+        for path in client_paths(output_dir, &client)? {
+            if path.exists() {
+                removed.push(path.clone());
+            }
+            changes.remove(path);
+        }
+    }
+    stage_server(&mut changes, output_dir, &server_config)?;
+    if !finish(&changes, dry_run)? {
+        return Ok(());
+    }
+
+    if verbose {
+        for path in &removed {
+            println!("Removed client config: {:?}", path);
+        }
+    }
+    for name in &names {
         println!("✓ Client '{}' revoked", name);
+    }
+
+    Ok(())
+}
+
+/// Add every client in a manifest in one transaction.
+///
+/// Each row is reported; if any fails, nothing is written.
+fn import_batch(output_dir: &Path, file: &Path, settings: &Settings, verbose: bool, dry_run: bool) -> Result<()> {
+    let mut server_config = load_server(output_dir)?;
+    let rows = batch::read_manifest(file)?;
+    if rows.is_empty() {
+        anyhow::bail!("No clients in {:?}", file);
+    }
+
+    let total = rows.len();
+    let mut clients = Vec::new();
+    let mut failed = 0;
+    for (i, row) in rows.into_iter().enumerate() {
+        let result = row.and_then(|row| {
+            let mut client = server_config.new_client(
+                &row.name,
+                row.ip,
+                row.pool.as_deref(),
+                row.full_tunnel(settings.tunnel.unwrap_or_default()),
+                crypto::generate_keypair()?,
+            )?;
+            client.owner = row.owner.clone();
+            client.tags = row.tags();
+            client.dns = settings.dns.clone();
+            client.keepalive = settings.keepalive;
+            client.format = settings.format.unwrap_or_default();
+            server_config.add_client(&client)?;
+            Ok(client)
+        });
+        match result {
+            Ok(client) => clients.push((i + 1, client)),
+            Err(e) => {
+                println!("  ✗ row {}: {:#}", i + 1, e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!("{} of {} row(s) failed; no clients were added", failed, total);
+    }
+
+    // Stage updated server state and every client config at once
+    let mut changes = ChangeSet::new();
+    stage_server(&mut changes, output_dir, &server_config)?;
+    let mut paths = Vec::new();
+    for (_, client) in &clients {
+        changes.note(format!("+ peer '{}' {}", client.name, client.ip));
+        paths.push(stage_client(&mut changes, output_dir, client)?);
+    }
+    if !finish(&changes, dry_run)? {
+        return Ok(());
+    }
+    // Rows are only reported once every one of them has been written
+    for ((row, client), path) in clients.iter().zip(&paths) {
+        println!("  ✓ row {}: '{}' {}", row, client.name, client.ip);
+        if verbose {
+            println!("  Config: {:?}", path);
+        }
+    }
+    println!("✓ Imported {} client(s) from {:?}", clients.len(), file);
+
+    Ok(())
+}
+
+/// Reconcile the server state with a desired-state file, printing the plan first
+fn sync_state(output_dir: &Path, file: &Path, verbose: bool, dry_run: bool) -> Result<()> {
+    let mut server_config = load_server(output_dir)?;
+    let desired = sync::read_desired(file)?;
+    let before = server_config.clients.clone();
+    let plan = sync::reconcile(&mut server_config, &desired)?;
+    if plan.is_empty() {
+        println!("✓ Already in sync with {:?}", file);
+        return Ok(());
+    }
+
+    let mut changes = ChangeSet::new();
+    for line in &plan {
+        changes.note(line.clone());
+    }
+    if !dry_run {
+        println!("Plan:");
+        for line in &plan {
+            println!("  {}", line);
+        }
+    }
+
+    // Revoked clients lose their files; everything else is re-rendered
+    for client in before.iter().filter(|b| !server_config.clients.iter().any(|c| c.name == b.name)) {
+        for path in client_paths(output_dir, client)? {
+            changes.remove(path);
+        }
+    }
+    stage_server(&mut changes, output_dir, &server_config)?;
+    for client in &server_config.clients {
+        stage_client(&mut changes, output_dir, client)?;
+    }
+    if !finish(&changes, dry_run)? {
+        return Ok(());
+    }
+
+    if verbose {
+        for change in changes.changes() {
+            println!("Wrote {:?}", change.path());
+        }
+    }
+    println!("✓ Synced {} change(s) from {:?}", plan.len(), file);
+
+    Ok(())
+}
+
+fn server_command(output_dir: &Path, command: ServerCommands, verbose: bool, dry_run: bool) -> Result<()> {
+    match command {
+        ServerCommands::Renumber { network } => renumber(output_dir, &network, verbose, dry_run),
+    }
+}
+
+/// Move the server and all clients to a new network
+fn renumber(output_dir: &Path, network: &str, verbose: bool, dry_run: bool) -> Result<()> {
+    let mut server_config = load_server(output_dir)?;
+    let plan = server_config.renumber(network)?;
+    check_host_routes(&server_config.network, &server_config.wg_interface)?;
+
+    let mut changes = ChangeSet::new();
+    for line in &plan {
+        changes.note(line.clone());
+    }
+    if !dry_run {
+        println!("Plan:");
+        for line in &plan {
+            println!("  {}", line);
+        }
+    }
+
+    stage_server(&mut changes, output_dir, &server_config)?;
+    for client in &server_config.clients {
+        stage_client(&mut changes, output_dir, client)?;
+    }
+    if !finish(&changes, dry_run)? {
+        return Ok(());
+    }
+
+    if verbose {
+        for change in changes.changes() {
+            println!("Wrote {:?}", change.path());
+        }
+    }
+    println!("✓ Renumbered to {}", server_config.network);
+    println!("  Redistribute all {} client config(s) and restart the server", server_config.clients.len());
+
+    Ok(())
+}
+
+/// Write an existing client's (or the server's) config, in its own format
+/// unless another is chosen
+#[allow(clippy::too_many_arguments)]
+fn export_config(
+    output_dir: &Path,
+    name: Option<&str>,
+    render: &RenderArgs,
+    options: &ExportOptions,
+    stdout: bool,
+    #[cfg(feature = "qr")] qr_out: Option<&Path>,
+    verbose: bool,
+    dry_run: bool,
+) -> Result<()> {
+    let mut server_config = load_server(output_dir)?;
+    let Some(name) = name else {
+        if render.full_tunnel || render.split_tunnel {
+            anyhow::bail!("--full-tunnel and --split-tunnel only apply to clients");
+        }
+        if let Some(format) = render.format {
+            server_config.format = format;
+            server_config.template = None;
+        }
+        if render.template.is_some() {
+            server_config.template = render.template.clone();
+        }
+        let files = server_renderer(output_dir, &server_config)?.render_server(&server_config, options)?;
+        let files = encrypt_files(files, &options.encrypt_to)?;
+        output_files(output_dir, "Server", files, ChangeSet::new(), stdout, verbose, dry_run)?;
+        return Ok(());
+    };
+
+    let mut client = stored_client(&server_config, name, render)?;
+    if !options.encrypt_to.is_empty() {
+        client.encrypt_to = options.encrypt_to.clone();
+    }
+    let files = client_files(output_dir, &client, options)?;
+    let label = format!("Client '{}'", client.name);
+    #[cfg(feature = "qr")]
+    {
+        let mut changes = ChangeSet::new();
+        let qr_path = stage_qr(&mut changes, output_dir, &client, qr_out)?;
+        if output_files(output_dir, &label, files, changes, stdout, verbose, dry_run)?
+            && let Some(path) = qr_path
+        {
+            println!("✓ QR code written to {:?}", path);
+        }
+    }
+    #[cfg(not(feature = "qr"))]
+    output_files(output_dir, &label, files, ChangeSet::new(), stdout, verbose, dry_run)?;
+
+    Ok(())
+}
+
+/// Print rendered files, or write them to the output directory together with
+/// the files already staged in `changes`.
+///
+/// Returns whether anything was written.
+fn output_files(
+    output_dir: &Path,
+    label: &str,
+    files: Vec<RenderedFile>,
+    mut changes: ChangeSet,
+    stdout: bool,
+    verbose: bool,
+    dry_run: bool,
+) -> Result<bool> {
+    if stdout {
+        for file in &files {
+            if files.len() > 1 {
+                println!("# {}", file.name);
+            }
+            print!("{}", file.contents);
+        }
+        return Ok(false);
+    }
+
+    let paths: Vec<PathBuf> = files.iter().map(|f| output_dir.join(&f.name)).collect();
+    for (file, path) in files.into_iter().zip(&paths) {
+        changes.write(path, file.contents);
+    }
+    if !finish(&changes, dry_run)? {
+        return Ok(false);
+    }
+
+    for path in &paths {
+        if verbose {
+            println!("Exported {:?}", path);
+        } else {
+            println!("✓ {} exported to {:?}", label, path);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Import, show or re-export clients
+fn client_command(
+    home: &Path,
+    output_dir: &Path,
+    command: ClientCommands,
+    settings: &Settings,
+    verbose: bool,
+    dry_run: bool,
+) -> Result<()> {
+    match command {
+        ClientCommands::ImportBatch { file } => import_batch(output_dir, &file, settings, verbose, dry_run)?,
+        ClientCommands::Show {
+            name,
+            render,
+            #[cfg(feature = "qr")]
+            qr,
+        } => {
+            let client = stored_client(&load_server(output_dir)?, &name, &render)?;
+            #[cfg(feature = "qr")]
+            if qr {
+                let config = client.to_wireguard_config()?;
+                qr::encode(&config, qr::EcLevel::default())?;
+                return qr2term::print_qr(config)
+                    .map_err(|e| anyhow::anyhow!("Failed to generate QR code: {}", e));
+            }
+            let files = client_renderer(output_dir, &client)?
+                .render_client(&client, &ExportOptions::default())?;
+            output_files(output_dir, "", files, ChangeSet::new(), true, verbose, dry_run)?;
+        }
+        ClientCommands::Rename { name, new_name } => rename_client(output_dir, &name, &new_name, verbose, dry_run)?,
+        ClientCommands::Move { name, to } => move_client(home, output_dir, &name, &to, verbose, dry_run)?,
+    }
+
+    Ok(())
+}
+
+/// Rename a client and its config files
+fn rename_client(output_dir: &Path, name: &str, new_name: &str, verbose: bool, dry_run: bool) -> Result<()> {
+    let mut server_config = load_server(output_dir)?;
+    let old = match server_config.clients.iter().find(|c| c.name == name) {
+        Some(client) => client.clone(),
+        None => anyhow::bail!("Client '{}' not found", name),
+    };
+    server_config.rename_client(name, new_name)?;
+
+    let mut changes = ChangeSet::new();
+    changes.note(format!("~ peer '{}' renamed to '{}'", name, new_name));
+    for path in client_paths(output_dir, &old)? {
+        changes.remove(path);
+    }
+    stage_server(&mut changes, output_dir, &server_config)?;
+    let client = server_config.clients.iter().find(|c| c.name == new_name).context("Renamed client missing")?;
+    let path = stage_client(&mut changes, output_dir, client)?;
+    if !finish(&changes, dry_run)? {
+        return Ok(());
+    }
+
+    if verbose {
+        println!("Wrote client config: {:?}", path);
+    }
+    println!("✓ Client '{}' renamed to '{}'", name, new_name);
+
+    Ok(())
+}
+
+/// Move a client with its keys from this context's server to another's
+fn move_client(home: &Path, output_dir: &Path, name: &str, to: &str, verbose: bool, dry_run: bool) -> Result<()> {
+    context::validate_name(to)?;
+    let target_dir = context::dir(home, to);
+    if target_dir == output_dir {
+        anyhow::bail!("Client '{}' is already in context '{}'", name, to);
+    }
+    if !context::exists(home, to) {
+        anyhow::bail!("Context '{}' is not initialized", to);
+    }
+    let mut source = load_server(output_dir)?;
+    let mut target = load_server(&target_dir)?;
+    let client = match source.clients.iter().find(|c| c.name == name) {
+        Some(client) => client.clone(),
+        None => anyhow::bail!("Client '{}' not found", name),
+    };
+    source.remove_client(name)?;
+    let moved = target.adopt_client(&client, &source.network)?;
+
+    let mut changes = ChangeSet::new();
+    changes.note(format!("- peer '{}' {}", name, client.ip));
+    changes.note(format!("+ peer '{}' {} in context '{}'", name, moved.ip, to));
+    for path in client_paths(output_dir, &client)? {
+        changes.remove(path);
+    }
+    // The target state is written first: if writing the source fails, the
+    // client is in both contexts rather than in neither
+    stage_server(&mut changes, &target_dir, &target)?;
+    stage_server(&mut changes, output_dir, &source)?;
+    let path = stage_client(&mut changes, &target_dir, &moved)?;
+    if !finish(&changes, dry_run)? {
+        return Ok(());
+    }
+
+    if verbose {
+        println!("Wrote client config: {:?}", path);
+    }
+    println!("✓ Client '{}' moved to context '{}' with IP {}", name, to, moved.ip);
+    println!("  Redistribute its config and restart both servers");
+
+    Ok(())
+}
+
+/// A stored client with the format and tunnel mode overrides applied.
+///
+/// The copy is only rendered; the server state is not changed.
+fn stored_client(server: &ServerConfig, name: &str, args: &RenderArgs) -> Result<ClientConfig> {
+    let mut client = match server.clients.iter().find(|c| c.name == name) {
+        Some(client) => client.clone(),
+        None => anyhow::bail!("Client '{}' not found", name),
+    };
+    if let Some(format) = args.format {
+        client.format = format;
+        client.template = None;
+    }
+    if args.template.is_some() {
+        client.template = args.template.clone();
+    }
+    if args.full_tunnel {
+        client.allowed_ips = "0.0.0.0/0".to_string();
+    } else if args.split_tunnel {
+        client.allowed_ips = server.network.clone();
+    }
+    Ok(client)
+}
+
+/// Turn export arguments into render options
+fn export_options(args: ExportArgs) -> Result<ExportOptions> {
+    Ok(ExportOptions {
+        private_key_file: args.private_key_file,
+        secret_name: args.secret_name,
+        namespace: args.namespace,
+        labels: container::parse_labels(&args.labels)?,
+        #[cfg(feature = "age")]
+        encrypt_to: args.encrypt_to,
+        #[cfg(not(feature = "age"))]
+        encrypt_to: Vec::new(),
+    })
+}
+
+/// Compare rendered configs on disk with what the server state would produce
+fn check_files(output_dir: &Path, verbose: bool) -> Result<()> {
+    let server_config = load_server(output_dir)?;
+    // A server config that render would refuse to overwrite is reported and
+    // compared with the config that would replace it
+    let refused = stage_server_conf(&mut ChangeSet::new(), output_dir, &server_config, false).err();
+    let mut changes = ChangeSet::new();
+    let server_conf = stage_rendered(&mut changes, output_dir, &server_config, refused.is_some())?;
+
+    let mut drifted = 0;
+    for change in changes.changes() {
+        let drift = change.drift()?;
+        let refusal = refused.as_ref().filter(|_| change.path() == server_conf);
+        let status = match (refusal, drift) {
+            (Some(err), _) => format!("✗ {}; run 'rusty-wire render --adopt' to replace it", err.root_cause()),
+            (None, Drift::UpToDate) => "✓ up to date".to_string(),
+            (None, Drift::Modified) => "✗ modified".to_string(),
+            (None, Drift::Missing) => "✗ missing".to_string(),
+            (None, Drift::Unexpected) => "✗ should not exist".to_string(),
+        };
+        println!("  {:?}: {}", change.path(), status);
+        if refusal.is_some() || drift != Drift::UpToDate {
+            drifted += 1;
+            if verbose && let Some(diff) = change.diff()? {
+                print!("{}", diff);
+            }
+        }
+    }
+
+    if drifted > 0 {
+        anyhow::bail!(
+            "{} file(s) differ from server state. Run 'rusty-wire render' to regenerate them.",
+            drifted
+        );
+    }
+    println!("✓ All configs match server state");
+
+    Ok(())
+}
+
+/// Report AllowedIPs problems in the server state
+fn lint_state(output_dir: &Path) -> Result<()> {
+    let server_config = load_server(output_dir)?;
+    let files = server_renderer(output_dir, &server_config)?.render_server(&server_config, &ExportOptions::default())?;
+    let path = output_dir.join(&files[0].name);
+    let hand_written = if path.exists() {
+        let existing = fs::read_to_string(&path).with_context(|| format!("Failed to read {:?}", path))?;
+        managed::hand_written(&existing).with_context(|| format!("Failed to parse {:?}", path))?
     } else {
-        anyhow::bail!("Client '{}' not found", name);
+        String::new()
+    };
+    let findings = routes::lint(&server_config, &hand_written)?;
+    for finding in &findings {
+        println!("  ✗ {}", finding);
+    }
+    if !findings.is_empty() {
+        anyhow::bail!("{} AllowedIPs problem(s) found", findings.len());
     }
+    println!("✓ No overlapping AllowedIPs");
 
     Ok(())
 }
 
-/// Show server configuration
-fn show_server(output_dir: &PathBuf) -> Result<()> {
-    // Load server config
-    let config_path = output_dir.join("wg-server.json");
+/// Regenerate every rendered config from the server state
+fn render_files(output_dir: &Path, adopt: bool, verbose: bool, dry_run: bool) -> Result<()> {
+    let server_config = load_server(output_dir)?;
+    let mut changes = ChangeSet::new();
+    stage_rendered(&mut changes, output_dir, &server_config, adopt)?;
+    if server_config.files != rendered_file_names(output_dir, &server_config)? {
+        stage_state(&mut changes, output_dir, &server_config)?;
+    }
+
+    let mut rendered = Vec::new();
+    for change in changes.changes() {
+        match change.drift()? {
+            Drift::UpToDate => {}
+            Drift::Unexpected => rendered.push(("-", change.path().to_path_buf())),
+            Drift::Modified | Drift::Missing => rendered.push(("~", change.path().to_path_buf())),
+        }
+    }
+    for (sign, path) in &rendered {
+        changes.note(format!("{} {}", sign, path.display()));
+    }
+    if !finish(&changes, dry_run)? {
+        return Ok(());
+    }
+
+    if verbose {
+        for (sign, path) in &rendered {
+            match *sign {
+                "-" => println!("Removed {:?}", path),
+                _ => println!("Rendered {:?}", path),
+            }
+        }
+    }
+    println!("✓ Updated {} file(s) from server state", rendered.len());
+
+    Ok(())
+}
+
+/// Write a client's wg-quick config as a QR code image
+#[cfg(feature = "qr")]
+fn write_qr(
+    output_dir: &Path,
+    name: &str,
+    file: Option<PathBuf>,
+    options: qr::QrOptions,
+    dry_run: bool,
+) -> Result<()> {
+    let server_config = load_server(output_dir)?;
+    let client = match server_config.clients.iter().find(|c| c.name == name) {
+        Some(client) => client,
+        None => anyhow::bail!("Client '{}' not found", name),
+    };
+    let path = output_dir.join(file.unwrap_or_else(|| PathBuf::from(format!("{}.png", name))));
+
+    // Mobile apps only import wg-quick configs, whatever format the client uses
+    let mut changes = ChangeSet::new();
+    changes.note(format!("+ QR code for '{}'", name));
+    changes.write_bytes(&path, qr::image(&path, &client.to_wireguard_config()?, options)?);
+    if !finish(&changes, dry_run)? {
+        return Ok(());
+    }
+    println!("✓ QR code for '{}' written to {:?}", name, path);
+
+    Ok(())
+}
+
+/// Write a client's onboarding bundle
+fn write_bundle(
+    output_dir: &Path,
+    name: &str,
+    file: Option<PathBuf>,
+    format: BundleFormat,
+    include: &[Format],
+    password_file: Option<&Path>,
+    dry_run: bool,
+) -> Result<()> {
+    let server_config = load_server(output_dir)?;
+    let client = match server_config.clients.iter().find(|c| c.name == name) {
+        Some(client) => client,
+        None => anyhow::bail!("Client '{}' not found", name),
+    };
+    let password = password_file.map(read_password).transpose()?;
+
+    let mut bundle = bundle::Bundle::new(client, include)?;
+    bundle.qr = bundle_qr(&bundle.config);
+    let contents = bundle.render(format, password.as_deref())?;
+
+    let path = file.unwrap_or_else(|| output_dir.join(format!("{}-bundle.{}", name, format.extension())));
+    let mut changes = ChangeSet::new();
+    changes.note(format!("+ bundle for '{}'", name));
+    changes.write_bytes(&path, contents);
+    if !finish(&changes, dry_run)? {
+        return Ok(());
+    }
+    println!("✓ Bundle for '{}' written to {:?}", name, path);
+
+    Ok(())
+}
+
+/// QR images of a bundled config, or none with a warning if it cannot be encoded
+#[cfg(feature = "qr")]
+fn bundle_qr(config: &str) -> Option<bundle::QrImages> {
+    let options = qr::QrOptions::default();
+    let images = qr::png(config, options).and_then(|png| {
+        Ok(bundle::QrImages {
+            png,
+            svg: qr::svg(config, options)?,
+        })
+    });
+    match images {
+        Ok(images) => Some(images),
+        Err(e) => {
+            eprintln!("Warning: bundling without a QR code: {}", e);
+            None
+        }
+    }
+}
+
+/// Without the `qr` feature bundles have no QR code, which is worth a warning
+#[cfg(not(feature = "qr"))]
+fn bundle_qr(_config: &str) -> Option<bundle::QrImages> {
+    eprintln!(
+        "Warning: bundling without a QR code: rusty-wire was built without the 'qr' feature"
+    );
+    None
+}
+
+/// Read a password from the first line of a file, or stdin for `-`
+fn read_password(path: &Path) -> Result<String> {
+    let contents = if path == Path::new("-") {
+        let mut input = String::new();
+        io::stdin().read_to_string(&mut input).context("Failed to read password from stdin")?;
+        input
+    } else {
+        fs::read_to_string(path).with_context(|| format!("Failed to read password file {:?}", path))?
+    };
+    match contents.lines().next() {
+        Some(password) if !password.is_empty() => Ok(password.to_string()),
+        _ => anyhow::bail!("Password file {:?} is empty", path),
+    }
+}
+
+/// Copy `wg show` output from stdin to stdout, labelling known peers.
+///
+/// Handles both the human-readable format (`peer: <key>`) and `wg show dump`,
+/// where a name column is appended to each peer line.
+fn annotate(output_dir: &Path) -> Result<()> {
+    let server_config = load_server(output_dir)?;
+    let mut input = String::new();
+    io::stdin()
+        .read_to_string(&mut input)
+        .context("Failed to read wg show output from stdin")?;
+
+    for line in input.lines() {
+        if let Some(key) = line.trim_start().strip_prefix("peer: ")
+            && let Some(client) = server_config.client_by_public_key(key.trim())
+        {
+            println!("{} ({})", line, client.label());
+        } else if let Some(client) = line
+            .split('\t')
+            .next()
+            .and_then(|key| server_config.client_by_public_key(key))
+        {
+            println!("{}\t{}", line, client.name);
+        } else {
+            println!("{}", line);
+        }
+    }
+
+    Ok(())
+}
+
+/// Load the server state from `wg-server.json`
+fn load_server(output_dir: &Path) -> Result<ServerConfig> {
+    let config_path = output_dir.join(context::STATE_FILE);
     if !config_path.exists() {
         anyhow::bail!("No server configuration found. Run 'rusty-wire init' first.");
     }
     let config_data = fs::read_to_string(&config_path)?;
-    let server_config: ServerConfig = serde_json::from_str(&config_data)?;
+    serde_json::from_str(&config_data)
+        .with_context(|| format!("Failed to parse server config {:?}", config_path))
+}
+
+/// Render a server config file from managed `blocks`, keeping any
+/// hand-written content outside them unless `adopt` is given.
+///
+/// `saved` are the blocks the saved state renders, which a file without
+/// markers must match to be taken over.
+fn render_managed(path: &Path, saved: &[managed::Block], blocks: &[managed::Block], adopt: bool) -> Result<String> {
+    if adopt || !path.exists() {
+        return Ok(managed::render(blocks));
+    }
+    let existing = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {:?}", path))?;
+    managed::merge(&existing, saved, blocks).with_context(|| {
+        format!(
+            "Refusing to overwrite {:?}: restore its managed block markers, or run \
+             'rusty-wire render --adopt' to replace it with the generated config",
+            path
+        )
+    })
+}
+
+/// The renderer for a built-in format, or for a user template if one is named
+fn renderer(output_dir: &Path, format: Format, template: Option<&str>) -> Result<Box<dyn Renderer>> {
+    Ok(match template {
+        Some(name) => Box::new(TemplateRenderer::new(output_dir.join(template::TEMPLATE_DIR), name)?),
+        None => Box::new(format),
+    })
+}
+
+/// The renderer chosen for the server at `init` time
+fn server_renderer(output_dir: &Path, server: &ServerConfig) -> Result<Box<dyn Renderer>> {
+    renderer(output_dir, server.format, server.template.as_deref())
+}
+
+/// The renderer chosen for a client when it was added
+fn client_renderer(output_dir: &Path, client: &ClientConfig) -> Result<Box<dyn Renderer>> {
+    renderer(output_dir, client.format, client.template.as_deref())
+}
+
+/// Stage the server state file and rendered server config.
+///
+/// Returns the path of the main server config file.
+fn stage_server(changes: &mut ChangeSet, output_dir: &Path, server: &ServerConfig) -> Result<PathBuf> {
+    stage_state(changes, output_dir, server)?;
+    stage_server_conf(changes, output_dir, server, false)
+}
+
+/// Stage the server state file, recording the files rendered from it
+fn stage_state(changes: &mut ChangeSet, output_dir: &Path, server: &ServerConfig) -> Result<()> {
+    let mut state = server.clone();
+    state.files = rendered_file_names(output_dir, server)?;
+    changes.write(output_dir.join(context::STATE_FILE), serde_json::to_string_pretty(&state)?);
+    Ok(())
+}
+
+/// Names of the server's and every client's rendered files
+fn rendered_file_names(output_dir: &Path, server: &ServerConfig) -> Result<Vec<String>> {
+    let mut names: Vec<String> = server_renderer(output_dir, server)?
+        .render_server(server, &ExportOptions::default())?
+        .into_iter()
+        .map(|file| file.name)
+        .collect();
+    for client in &server.clients {
+        names.extend(client_file_names(output_dir, client)?);
+    }
+    Ok(names)
+}
+
+/// Stage the server config in its chosen format.
+///
+/// Returns the path of the first file written.
+fn stage_server_conf(changes: &mut ChangeSet, output_dir: &Path, server: &ServerConfig, adopt: bool) -> Result<PathBuf> {
+    let files = server_conf_files(output_dir, server)?;
+    // What the saved state renders is what wrote the files on disk, before
+    // the changes being staged
+    let saved = match output_dir.join(context::STATE_FILE).exists() {
+        true => server_conf_files(output_dir, &load_server(output_dir)?)?,
+        false => Vec::new(),
+    };
+    let first = output_dir.join(&files[0].0.name);
+    for (file, blocks) in &files {
+        let path = output_dir.join(&file.name);
+        let contents = match blocks {
+            Some(blocks) => {
+                let saved_blocks = saved
+                    .iter()
+                    .find(|(saved_file, _)| saved_file.name == file.name)
+                    .and_then(|(_, saved_blocks)| saved_blocks.as_deref())
+                    .unwrap_or(blocks);
+                render_managed(&path, saved_blocks, blocks, adopt)?
+            }
+            None => file.contents.clone(),
+        };
+        changes.write(path, contents);
+    }
+    Ok(first)
+}
+
+/// The server config files in the server's chosen format, each with its
+/// managed blocks if hand edits outside them are kept
+fn server_conf_files(output_dir: &Path, server: &ServerConfig) -> Result<Vec<(RenderedFile, Option<Vec<managed::Block>>)>> {
+    let files = server_renderer(output_dir, server)?.render_server(server, &ExportOptions::default())?;
+    files
+        .into_iter()
+        .map(|file| {
+            let blocks = match (server.format, &server.template) {
+                (Format::WgQuick, None) => Some(server.managed_blocks()?),
+                // Template output is one managed block, so hand edits survive too
+                (_, Some(_)) => Some(vec![managed::Block::new("template", file.contents.clone())]),
+                (_, None) => None,
+            };
+            Ok((file, blocks))
+        })
+        .collect()
+}
+
+/// Stage the server config and every client config rendered from the server
+/// state, and the removal of files rusty-wire wrote that no client has any more.
+///
+/// Returns the path of the server config.
+fn stage_rendered(changes: &mut ChangeSet, output_dir: &Path, server: &ServerConfig, adopt: bool) -> Result<PathBuf> {
+    let server_conf = stage_server_conf(changes, output_dir, server, adopt)?;
+    for client in &server.clients {
+        stage_client(changes, output_dir, client)?;
+    }
+    // Left behind by a hand-edited state or a format change: files the state
+    // recorded, and encrypted files next to their digests. Anything else in
+    // the output directory is not ours to remove.
+    let mut known = server.files.clone();
+    for entry in fs::read_dir(output_dir).with_context(|| format!("Failed to read {:?}", output_dir))? {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        if let Some(encrypted) = name.strip_suffix(DIGEST_SUFFIX)
+            && encrypted.ends_with(ENCRYPTED_SUFFIX)
+        {
+            known.push(encrypted.to_string());
+            known.push(name);
+        }
+    }
+    changes.remove_unstaged(output_dir, &known);
+    Ok(server_conf)
+}
+
+/// Added to a file's name when it is encrypted to age recipients
+const ENCRYPTED_SUFFIX: &str = ".age";
+
+/// Added to an encrypted file's name for the digest of what it encrypts
+const DIGEST_SUFFIX: &str = ".sha256";
+
+/// Stage a client's config files in its chosen format.
+///
+/// Encrypted files differ on every render, so each gets a `.sha256` file
+/// with a digest of what it encrypts, and is only rewritten when that changes.
+///
+/// Returns the path of the first file written.
+fn stage_client(changes: &mut ChangeSet, output_dir: &Path, client: &ClientConfig) -> Result<PathBuf> {
+    let files = client_renderer(output_dir, client)?.render_client(client, &ExportOptions::default())?;
+    let digests: Vec<String> =
+        files.iter().map(|file| encrypted_digest(&client.encrypt_to, &file.contents)).collect();
+    let files = encrypt_files(files, &client.encrypt_to)?;
+    let first = output_dir.join(&files[0].name);
+    for (file, digest) in files.into_iter().zip(digests) {
+        let path = output_dir.join(&file.name);
+        if client.encrypt_to.is_empty() {
+            changes.write(path, file.contents);
+            continue;
+        }
+        let digest_path = output_dir.join(format!("{}{}", file.name, DIGEST_SUFFIX));
+        let current = fs::read_to_string(&digest_path).ok();
+        if current.as_deref() == Some(digest.as_str()) {
+            changes.create(path, file.contents);
+        } else {
+            changes.write(path, file.contents);
+        }
+        changes.write(digest_path, digest);
+    }
+    Ok(first)
+}
+
+/// Every file `stage_client` writes for a client, for removing them
+fn client_paths(output_dir: &Path, client: &ClientConfig) -> Result<Vec<PathBuf>> {
+    Ok(client_file_names(output_dir, client)?.into_iter().map(|name| output_dir.join(name)).collect())
+}
+
+/// Names of every file `stage_client` writes for a client.
+///
+/// They follow from the format and recipients, so nothing is encrypted.
+fn client_file_names(output_dir: &Path, client: &ClientConfig) -> Result<Vec<String>> {
+    let names = client_renderer(output_dir, client)?.client_file_names(client, &ExportOptions::default())?;
+    if client.encrypt_to.is_empty() {
+        return Ok(names);
+    }
+    let mut encrypted = Vec::new();
+    for name in names {
+        encrypted.push(format!("{}{}", name, ENCRYPTED_SUFFIX));
+        encrypted.push(format!("{}{}{}", name, ENCRYPTED_SUFFIX, DIGEST_SUFFIX));
+    }
+    Ok(encrypted)
+}
+
+/// Digest of a file before it is encrypted to `recipients`, recorded next to
+/// the encrypted file to tell whether it is stale
+fn encrypted_digest(recipients: &[String], plaintext: &str) -> String {
+    let mut hasher = Sha256::new();
+    for recipient in recipients {
+        hasher.update(recipient.trim().as_bytes());
+        hasher.update(b"\n");
+    }
+    hasher.update(b"\n");
+    hasher.update(plaintext.as_bytes());
+    let hex: String = hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}\n", hex)
+}
+
+/// Render a client's config files, encrypted if it has age recipients
+fn client_files(output_dir: &Path, client: &ClientConfig, options: &ExportOptions) -> Result<Vec<RenderedFile>> {
+    let files = client_renderer(output_dir, client)?.render_client(client, options)?;
+    encrypt_files(files, &client.encrypt_to)
+}
+
+/// Encrypt files to age recipients, if any
+#[cfg(feature = "age")]
+fn encrypt_files(files: Vec<RenderedFile>, recipients: &[String]) -> Result<Vec<RenderedFile>> {
+    if recipients.is_empty() {
+        return Ok(files);
+    }
+    encrypt::encrypt_files(files, recipients)
+}
+
+/// Encrypt files to age recipients, if any
+#[cfg(not(feature = "age"))]
+fn encrypt_files(files: Vec<RenderedFile>, recipients: &[String]) -> Result<Vec<RenderedFile>> {
+    if recipients.is_empty() {
+        return Ok(files);
+    }
+    anyhow::bail!("Encrypting to age recipients needs rusty-wire built with --features age")
+}
+
+/// Apply staged changes, or print a preview when `dry_run` is set.
+///
+/// Returns whether the changes were applied.
+fn finish(changes: &ChangeSet, dry_run: bool) -> Result<bool> {
+    if dry_run {
+        print!("{}", changes.preview()?);
+        println!("\nDry run: no files were changed.");
+        return Ok(false);
+    }
+    changes.apply()?;
+    Ok(true)
+}
+
+/// Show server configuration
+fn show_server(output_dir: &Path) -> Result<()> {
+    // Load server config
+    let server_config = load_server(output_dir)?;
 
     // Print server config
     println!("Server Configuration:");
@@ -276,14 +1568,125 @@ fn show_server(output_dir: &PathBuf) -> Result<()> {
         server_config.endpoint, server_config.port
     );
     println!("  Network: {}", server_config.network);
+    println!("  Server IP: {}", server_config.server_ip()?);
     println!("  Interface: {}", server_config.interface);
+    println!("  WireGuard Interface: {}", server_config.wg_interface);
     println!("  Public Key: {}", server_config.keys.public);
     println!("  Clients: {}", server_config.clients.len());
 
-    let wg_config_path = output_dir.join("wg0.conf");
-    if wg_config_path.exists() {
-        println!("  WireGuard Config: {:?}", wg_config_path);
+    let renderer = server_renderer(output_dir, &server_config)?;
+    for file in renderer.render_server(&server_config, &ExportOptions::default())? {
+        let path = output_dir.join(file.name);
+        if path.exists() {
+            println!("  WireGuard Config: {:?}", path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Read a single key from stdin, as `wg pubkey` does
+fn read_stdin_key() -> Result<String> {
+    let mut input = String::new();
+    io::stdin()
+        .read_to_string(&mut input)
+        .context("Failed to read key from stdin")?;
+    Ok(input)
+}
+
+/// Search for a vanity keypair, reporting progress on stderr
+fn find_vanity_keypair(prefix: &str) -> Result<crypto::KeyPair> {
+    let expected = crypto::vanity_expected_attempts(prefix)?;
+    eprintln!(
+        "Searching for a public key starting with '{}' (~{:.0} attempts expected)",
+        prefix, expected
+    );
+
+    let started = Instant::now();
+    let keys = crypto::vanity_keypair(prefix, Duration::from_secs(1), |attempts| {
+        let rate = attempts as f64 / started.elapsed().as_secs_f64();
+        eprint!(
+            "\r  {} attempts ({:.1}% of expected, {:.0}/s)",
+            attempts,
+            attempts as f64 / expected * 100.0,
+            rate
+        );
+    })?;
+    eprintln!("\r\x1b[2K✓ Found after {:.1?}", started.elapsed());
+
+    Ok(keys)
+}
+
+/// Run a `key` subcommand
+fn key_command(command: KeyCommands) -> Result<()> {
+    match command {
+        KeyCommands::Genkey => {
+            let keys = crypto::generate_keypair()?;
+            println!("{}", keys.private);
+        }
+        KeyCommands::Pubkey => {
+            let private: PrivateKey = read_stdin_key()?.parse()?;
+            println!("{}", private.public_key());
+        }
+        KeyCommands::Genpsk => {
+            println!("{}", PresharedKey::generate());
+        }
+        KeyCommands::Verify { public_key } => {
+            let public: PublicKey = public_key.parse()?;
+            let private: PrivateKey = read_stdin_key()?.parse()?;
+            if private.public_key() != public {
+                anyhow::bail!("Private key does not match public key {}", public);
+            }
+            println!("✓ Key pair matches");
+        }
+        KeyCommands::Vanity { prefix } => {
+            let keys = find_vanity_keypair(&prefix)?;
+            println!("Private key: {}", keys.private);
+            println!("Public key:  {}", keys.public);
+        }
+        KeyCommands::Fingerprint { public_key } => {
+            let public: PublicKey = match public_key {
+                Some(key) => key.parse()?,
+                None => read_stdin_key()?.parse()?,
+            };
+            println!("{}", public.fingerprint());
+        }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::test_support;
+
+    #[test]
+    fn test_render_removes_only_its_own_files() {
+        let dir = std::env::temp_dir().join(format!("rusty-wire-render-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let mut server = test_support::server();
+        test_support::client(&mut server, "laptop", "10.0.0.0/24");
+        test_support::client(&mut server, "phone", "10.0.0.0/24");
+        let mut changes = ChangeSet::new();
+        stage_server(&mut changes, &dir, &server).unwrap();
+        stage_rendered(&mut changes, &dir, &server, false).unwrap();
+        changes.apply().unwrap();
+        fs::write(dir.join("wg9.conf"), "[Interface]\n").unwrap();
+        fs::write(dir.join("resolv.conf"), "nameserver 1.1.1.1\n").unwrap();
+
+        // Drop 'phone' from the state by hand, as an older version's revoke might
+        let mut state = load_server(&dir).unwrap();
+        state.clients.retain(|c| c.name != "phone");
+        fs::write(dir.join(context::STATE_FILE), serde_json::to_string_pretty(&state).unwrap()).unwrap();
+        render_files(&dir, false, false, false).unwrap();
+
+        assert!(!dir.join("phone.conf").exists());
+        assert!(dir.join("laptop.conf").exists());
+        assert!(dir.join("wg9.conf").exists());
+        assert!(dir.join("resolv.conf").exists());
+        assert_eq!(load_server(&dir).unwrap().files, ["wg0.conf", "laptop.conf"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,250 @@
+use anyhow::Result;
+
+/// Prefix of the comment line that opens a managed block
+const BEGIN: &str = "# BEGIN rusty-wire ";
+/// Prefix of the comment line that closes a managed block
+const END: &str = "# END rusty-wire ";
+/// Suffix appended to the opening marker as a warning to editors
+const BEGIN_NOTE: &str = " (managed, do not edit)";
+
+/// A named region of a config file owned by rusty-wire
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub name: &'static str,
+    pub content: String,
+}
+
+impl Block {
+    pub fn new(name: &'static str, content: impl Into<String>) -> Self {
+        Self {
+            name,
+            content: content.into(),
+        }
+    }
+}
+
+/// A piece of an existing file: either hand-written text or a managed block
+#[derive(Debug, PartialEq, Eq)]
+enum Segment {
+    Text(String),
+    Block(String),
+}
+
+/// Render a fresh file containing only managed blocks
+pub fn render(blocks: &[Block]) -> String {
+    let mut out = String::new();
+    for (i, block) in blocks.iter().enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        push_block(&mut out, block);
+    }
+    out
+}
+
+/// The hand-written text of `existing` outside its managed blocks.
+///
+/// A file without any blocks was generated whole and has none.
+pub fn hand_written(existing: &str) -> Result<String> {
+    let segments = parse(existing)?;
+    if !segments.iter().any(|s| matches!(s, Segment::Block(_))) {
+        return Ok(String::new());
+    }
+    Ok(segments
+        .into_iter()
+        .filter_map(|segment| match segment {
+            Segment::Text(text) => Some(text),
+            Segment::Block(_) => None,
+        })
+        .collect())
+}
+
+/// The lines of `text` that carry settings: neither blank nor comments
+fn settings(text: &str) -> Vec<&str> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect()
+}
+
+/// Replace the managed blocks in `existing`, keeping everything outside them.
+///
+/// A file without any markers is only taken over when it holds the same
+/// settings as `saved`, the blocks it was generated from. Comments are not
+/// compared, since files written before managed blocks carried other peer
+/// comments. Any other difference may be a hand-written line and is an
+/// error, as are damaged markers, so nothing is silently overwritten.
+pub fn merge(existing: &str, saved: &[Block], blocks: &[Block]) -> Result<String> {
+    let segments = parse(existing)?;
+    if !segments.iter().any(|s| matches!(s, Segment::Block(_))) {
+        let generated: Vec<&str> = saved.iter().flat_map(|b| settings(&b.content)).collect();
+        if settings(existing) != generated {
+            anyhow::bail!("File has no managed block markers and differs from the generated config");
+        }
+        return Ok(render(blocks));
+    }
+
+    let mut seen = Vec::new();
+    let mut out = String::new();
+    for segment in &segments {
+        match segment {
+            Segment::Text(text) => out.push_str(text),
+            Segment::Block(name) => {
+                let block = blocks
+                    .iter()
+                    .find(|b| b.name == name)
+                    .ok_or_else(|| anyhow::anyhow!("Unknown managed block '{}'", name))?;
+                if seen.contains(&block.name) {
+                    anyhow::bail!("Managed block '{}' appears more than once", name);
+                }
+                seen.push(block.name);
+                push_block(&mut out, block);
+            }
+        }
+    }
+
+    if let Some(missing) = blocks.iter().find(|b| !seen.contains(&b.name)) {
+        anyhow::bail!("Managed block '{}' is missing", missing.name);
+    }
+
+    Ok(out)
+}
+
+/// Append a block with its markers to `out`
+fn push_block(out: &mut String, block: &Block) {
+    out.push_str(&format!("{}{}{}\n", BEGIN, block.name, BEGIN_NOTE));
+    out.push_str(&block.content);
+    if !block.content.is_empty() && !block.content.ends_with('\n') {
+        out.push('\n');
+    }
+    out.push_str(&format!("{}{}\n", END, block.name));
+}
+
+/// Split a file into hand-written text and managed block placeholders
+fn parse(existing: &str) -> Result<Vec<Segment>> {
+    let mut segments = Vec::new();
+    let mut text = String::new();
+    let mut open: Option<(String, usize)> = None;
+
+    for (i, line) in existing.split_inclusive('\n').enumerate() {
+        let line_no = i + 1;
+        let trimmed = line.trim();
+        if let Some(rest) = trimmed.strip_prefix(BEGIN) {
+            let name = marker_name(rest, line_no)?;
+            if let Some((open_name, open_line)) = &open {
+                anyhow::bail!(
+                    "Line {}: managed block '{}' starts before block '{}' (line {}) ends",
+                    line_no,
+                    name,
+                    open_name,
+                    open_line
+                );
+            }
+            if !text.is_empty() {
+                segments.push(Segment::Text(std::mem::take(&mut text)));
+            }
+            open = Some((name, line_no));
+        } else if let Some(rest) = trimmed.strip_prefix(END) {
+            let name = marker_name(rest, line_no)?;
+            match open.take() {
+                Some((open_name, _)) if open_name == name => segments.push(Segment::Block(name)),
+                Some((open_name, open_line)) => anyhow::bail!(
+                    "Line {}: end of managed block '{}' does not match '{}' (line {})",
+                    line_no,
+                    name,
+                    open_name,
+                    open_line
+                ),
+                None => anyhow::bail!(
+                    "Line {}: end of managed block '{}' without a matching start",
+                    line_no,
+                    name
+                ),
+            }
+        } else if open.is_none() {
+            text.push_str(line);
+        }
+    }
+
+    if let Some((name, line_no)) = open {
+        anyhow::bail!(
+            "Line {}: managed block '{}' is never closed",
+            line_no,
+            name
+        );
+    }
+    if !text.is_empty() {
+        segments.push(Segment::Text(text));
+    }
+
+    Ok(segments)
+}
+
+/// Extract the block name from the remainder of a marker line
+fn marker_name(rest: &str, line_no: usize) -> Result<String> {
+    match rest.split_whitespace().next() {
+        Some(name) => Ok(name.to_string()),
+        None => anyhow::bail!("Line {}: managed block marker has no name", line_no),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocks(interface: &str, peers: &str) -> Vec<Block> {
+        vec![Block::new("interface", interface), Block::new("peers", peers)]
+    }
+
+    #[test]
+    fn test_hand_written() {
+        let file = render(&blocks("[Interface]\n", "[Peer]\nA\n")) + "\n[Peer]\nB\n";
+        assert_eq!(hand_written(&file).unwrap(), "\n\n[Peer]\nB\n");
+        assert_eq!(hand_written("[Peer]\nA\n").unwrap(), "");
+    }
+
+    #[test]
+    fn test_merge_preserves_hand_written_sections() {
+        let original = render(&blocks("[Interface]\n", "[Peer]\nA\n"));
+        let edited = original.replace(
+            "# BEGIN rusty-wire peers",
+            "PostUp = echo custom\n\n# BEGIN rusty-wire peers",
+        ) + "\n[Peer]\nManual\n";
+
+        let merged = merge(&edited, &[], &blocks("[Interface]\n", "[Peer]\nB\n")).unwrap();
+        assert!(merged.contains("PostUp = echo custom\n"));
+        assert!(merged.contains("[Peer]\nB\n"));
+        assert!(!merged.contains("[Peer]\nA\n"));
+        assert!(merged.ends_with("[Peer]\nManual\n"));
+    }
+
+    #[test]
+    fn test_merge_unmarked_file() {
+        // A file as generated before markers existed is taken over
+        let saved = blocks("[Interface]\n", "[Peer]\nA\n");
+        let updated = blocks("[Interface]\n", "[Peer]\nA\n\n[Peer]\nB\n");
+        let merged = merge("[Interface]\n\n[Peer]\nA\n", &saved, &updated).unwrap();
+        assert_eq!(merged, render(&updated));
+
+        // Comments and blank lines may differ
+        let commented = blocks("[Interface]\n", "# Name: a\n[Peer]\nA\n");
+        assert!(merge("# old comment\n[Interface]\n[Peer]\nA\n", &commented, &commented).is_ok());
+
+        // One with a custom PostUp or manual peer is not
+        let edited = "[Interface]\nPostUp = echo custom\n\n[Peer]\nA\n";
+        assert!(merge(edited, &saved, &saved).is_err());
+        assert!(merge("[Interface]\nold\n", &saved, &updated).is_err());
+    }
+
+    #[test]
+    fn test_merge_rejects_damaged_markers() {
+        let original = render(&blocks("[Interface]\n", "[Peer]\n"));
+        let unclosed = original.replace("# END rusty-wire peers\n", "");
+        let missing = original.replace("# BEGIN rusty-wire peers (managed, do not edit)\n[Peer]\n# END rusty-wire peers\n", "");
+        let duplicated = format!("{}{}", original, original);
+
+        assert!(merge(&unclosed, &[], &blocks("", "")).is_err());
+        assert!(merge(&missing, &[], &blocks("", "")).is_err());
+        assert!(merge(&duplicated, &[], &blocks("", "")).is_err());
+    }
+}
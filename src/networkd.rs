@@ -0,0 +1,202 @@
+use anyhow::Result;
+use std::path::Path;
+
+use crate::config::{ClientConfig, ServerConfig};
+
+/// Routing table used for full-tunnel clients, kept separate from `main` so
+/// the encrypted traffic to the endpoint does not loop into the tunnel
+const FULL_TUNNEL_TABLE: u32 = 1000;
+/// Firewall mark set on WireGuard's own packets for full-tunnel clients
+const FULL_TUNNEL_FWMARK: &str = "0x8888";
+
+/// A systemd-networkd `.netdev` and `.network` pair
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkdUnits {
+    pub netdev: String,
+    pub network: String,
+}
+
+/// Render configs as systemd-networkd units instead of wg-quick files
+pub trait NetworkdConfig {
+    /// Render the units for interface `ifname`.
+    ///
+    /// The netdev references the private key at `private_key_file` via
+    /// `PrivateKeyFile=`, so the units hold no secrets and can be world-readable.
+    fn to_networkd_config(&self, ifname: &str, private_key_file: &Path) -> Result<NetworkdUnits>;
+}
+
+impl NetworkdConfig for ServerConfig {
+    fn to_networkd_config(&self, ifname: &str, private_key_file: &Path) -> Result<NetworkdUnits> {
+        let mut netdev = format!(
+            "[NetDev]\n\
+             Name={}\n\
+             Kind=wireguard\n\
+             Description=WireGuard server (rusty-wire)\n\
+             \n\
+             [WireGuard]\n\
+             PrivateKeyFile={}\n\
+             ListenPort={}\n\
+             RouteTable=main\n",
+            ifname,
+            private_key_file.display(),
+            self.port
+        );
+        for client in &self.clients {
+            netdev.push_str(&format!(
+                "\n{}[WireGuardPeer]\n\
+                 PublicKey={}\n\
+                 AllowedIPs={}/32\n",
+                client.annotation(),
+                client.keys.public,
+                client.ip
+            ));
+        }
+
+        // IPMasquerade= replaces the iptables PostUp/PostDown rules and
+        // enables forwarding for the interface
+        let network = format!(
+            "[Match]\n\
+             Name={}\n\
+             \n\
+             [Network]\n\
+             Address={}\n\
+             IPMasquerade=ipv4\n",
+            ifname,
+            self.server_ip_with_cidr()?
+        );
+
+        Ok(NetworkdUnits { netdev, network })
+    }
+}
+
+impl NetworkdConfig for ClientConfig {
+    fn to_networkd_config(&self, ifname: &str, private_key_file: &Path) -> Result<NetworkdUnits> {
+        let full_tunnel = self.is_full_tunnel();
+        let mut netdev = format!(
+            "[NetDev]\n\
+             Name={}\n\
+             Kind=wireguard\n\
+             Description=WireGuard client {} (rusty-wire)\n\
+             \n\
+             [WireGuard]\n\
+             PrivateKeyFile={}\n",
+            ifname,
+            self.name,
+            private_key_file.display()
+        );
+        if full_tunnel {
+            netdev.push_str(&format!(
+                "FirewallMark={}\nRouteTable={}\n",
+                FULL_TUNNEL_FWMARK, FULL_TUNNEL_TABLE
+            ));
+        } else {
+            netdev.push_str("RouteTable=main\n");
+        }
+        netdev.push_str(&format!(
+            "\n[WireGuardPeer]\n\
+             PublicKey={}\n\
+             Endpoint={}:{}\n\
+             AllowedIPs={}\n\
+             PersistentKeepalive={}\n",
+            self.server_public_key,
+            self.server_endpoint,
+            self.server_port,
+            self.allowed_ip_list().join(","),
+            self.keepalive()
+        ));
+
+        let mut network = format!(
+            "[Match]\n\
+             Name={}\n\
+             \n\
+             [Network]\n\
+             Address={}/32\n",
+            ifname, self.ip
+        );
+        for dns in self.dns_servers() {
+            network.push_str(&format!("DNS={}\n", dns));
+        }
+        let mut domains = self.dns_search();
+        if full_tunnel {
+            // `~.` routes lookups for every domain to this link
+            domains.push("~.");
+        }
+        if !domains.is_empty() {
+            network.push_str(&format!("Domains={}\n", domains.join(" ")));
+        }
+        if full_tunnel {
+            // Send everything not marked by WireGuard itself to the tunnel table
+            network.push_str(&format!(
+                "DNSDefaultRoute=yes\n\
+                 \n\
+                 [RoutingPolicyRule]\n\
+                 FirewallMark={}\n\
+                 InvertRule=yes\n\
+                 Table={}\n\
+                 Priority=10\n",
+                FULL_TUNNEL_FWMARK, FULL_TUNNEL_TABLE
+            ));
+        }
+
+        Ok(NetworkdUnits { netdev, network })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_support;
+
+    const KEY_FILE: &str = "/etc/systemd/network/wg0.key";
+
+    fn server_with_client(allowed_ips: &str) -> (ServerConfig, ClientConfig) {
+        let mut server = test_support::server();
+        let client = test_support::client(&mut server, "laptop", allowed_ips);
+        (server, client)
+    }
+
+    #[test]
+    fn test_server_units() {
+        let (server, client) = server_with_client("10.0.0.0/24");
+        let units = server.to_networkd_config("wg0", Path::new(KEY_FILE)).unwrap();
+
+        assert!(units.netdev.contains("Kind=wireguard\n"));
+        assert!(units.netdev.contains("ListenPort=51820\n"));
+        assert!(units.netdev.contains("PrivateKeyFile=/etc/systemd/network/wg0.key\n"));
+        assert!(!units.netdev.contains(&server.keys.private.to_string()));
+        assert!(units.netdev.contains(&format!(
+            "# Name: laptop\n# Created: {}\n[WireGuardPeer]\nPublicKey={}\nAllowedIPs=10.0.0.2/32\n",
+            client.created.as_deref().unwrap(),
+            client.keys.public
+        )));
+        assert!(units.network.contains("Address=10.0.0.1/24\n"));
+        assert!(units.network.contains("IPMasquerade=ipv4\n"));
+        assert!(!units.network.contains("iptables"));
+    }
+
+    #[test]
+    fn test_client_split_and_full_tunnel() {
+        let (_, split) = server_with_client("10.0.0.0/24");
+        let units = split.to_networkd_config("wg0", Path::new(KEY_FILE)).unwrap();
+        assert!(!units.netdev.contains(&split.keys.private.to_string()));
+        assert!(units.netdev.contains("RouteTable=main\n"));
+        assert!(units.netdev.contains("Endpoint=vpn.example.com:51820\n"));
+        assert!(units.network.contains("DNS=1.1.1.1\nDNS=9.9.9.9\n"));
+        assert!(!units.network.contains("[RoutingPolicyRule]"));
+
+        let (_, full) = server_with_client("0.0.0.0/0");
+        let units = full.to_networkd_config("wg0", Path::new(KEY_FILE)).unwrap();
+        assert!(units.netdev.contains("FirewallMark=0x8888\nRouteTable=1000\n"));
+        assert!(units.network.contains("Domains=~.\nDNSDefaultRoute=yes\n"));
+        assert!(units.network.contains("[RoutingPolicyRule]\nFirewallMark=0x8888\nInvertRule=yes\nTable=1000\n"));
+    }
+
+    #[test]
+    fn test_client_search_domains() {
+        let (_, mut client) = server_with_client("0.0.0.0/0");
+        client.dns = Some(vec!["10.0.0.1".to_string(), "corp.example".to_string()]);
+        let units = client.to_networkd_config("wg0", Path::new(KEY_FILE)).unwrap();
+        assert!(units.network.contains("DNS=10.0.0.1\nDomains=corp.example ~.\n"));
+        assert!(!units.network.contains("DNS=corp.example"));
+    }
+}
@@ -0,0 +1,145 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::Ipv4Addr;
+
+/// An inclusive range of IPv4 addresses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressRange {
+    pub start: Ipv4Addr,
+    pub end: Ipv4Addr,
+}
+
+impl AddressRange {
+    /// Parse `10.0.0.0/24`, `10.0.0.10-10.0.0.20` or a single address
+    pub fn parse(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if let Some((ip, len)) = s.split_once('/') {
+            let ip: Ipv4Addr = ip.parse().with_context(|| format!("Invalid IP address in '{}'", s))?;
+            let len: u32 = len.parse().with_context(|| format!("Invalid prefix length in '{}'", s))?;
+            if len > 32 {
+                anyhow::bail!("Invalid prefix length in '{}'", s);
+            }
+            let mask = u32::MAX.checked_shl(32 - len).unwrap_or(0);
+            let start = u32::from(ip) & mask;
+            return Ok(Self {
+                start: Ipv4Addr::from(start),
+                end: Ipv4Addr::from(start | !mask),
+            });
+        }
+        let (start, end) = s.split_once('-').unwrap_or((s, s));
+        let range = Self {
+            start: start.trim().parse().with_context(|| format!("Invalid IP address in '{}'", s))?,
+            end: end.trim().parse().with_context(|| format!("Invalid IP address in '{}'", s))?,
+        };
+        if range.start > range.end {
+            anyhow::bail!("Range '{}' ends before it starts", s);
+        }
+        Ok(range)
+    }
+
+    pub fn contains(&self, ip: Ipv4Addr) -> bool {
+        self.start <= ip && ip <= self.end
+    }
+
+    pub fn contains_range(&self, other: &AddressRange) -> bool {
+        self.contains(other.start) && self.contains(other.end)
+    }
+
+    pub fn overlaps(&self, other: &AddressRange) -> bool {
+        self.start <= other.end && other.start <= self.end
+    }
+}
+
+impl fmt::Display for AddressRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, self.end)
+        }
+    }
+}
+
+/// A named block of client addresses inside the server network
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pool {
+    pub name: String,
+    pub network: String,
+    /// Next address to try, so revoked IPs are not handed out again at once
+    next_ip: u32,
+}
+
+impl Pool {
+    pub fn new(name: &str, network: &str) -> Result<Self> {
+        let range = AddressRange::parse(network)?;
+        if !network.contains('/') {
+            anyhow::bail!("Pool network must be in CIDR notation (e.g., 10.0.0.128/26)");
+        }
+        Ok(Self {
+            name: name.to_string(),
+            network: network.to_string(),
+            next_ip: u32::from(range.start),
+        })
+    }
+
+    pub fn range(&self) -> Result<AddressRange> {
+        AddressRange::parse(&self.network)
+    }
+
+    /// The next address in the pool for which `taken` is false
+    pub fn allocate(&mut self, taken: impl Fn(Ipv4Addr) -> bool) -> Result<Ipv4Addr> {
+        let range = self.range()?;
+        allocate(&mut self.next_ip, range, taken)
+            .with_context(|| format!("Pool '{}' ({}) has no free addresses", self.name, self.network))
+    }
+}
+
+/// Scan from `cursor` to the end of `range`, then from its start back to
+/// `cursor`, for an address that is not `taken`, leaving `cursor` after it
+pub fn allocate(cursor: &mut u32, range: AddressRange, taken: impl Fn(Ipv4Addr) -> bool) -> Option<Ipv4Addr> {
+    let (first, last) = (u32::from(range.start), u32::from(range.end));
+    let start = (*cursor).clamp(first, last);
+    for ip in (start..=last).chain(first..start) {
+        let ip = Ipv4Addr::from(ip);
+        if !taken(ip) {
+            *cursor = u32::from(ip).saturating_add(1);
+            return Some(ip);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ranges() {
+        let cidr = AddressRange::parse("10.0.0.130/26").unwrap();
+        assert_eq!(cidr.to_string(), "10.0.0.128-10.0.0.191");
+        let span = AddressRange::parse("10.0.0.200 - 10.0.0.210").unwrap();
+        assert!(span.contains("10.0.0.205".parse().unwrap()));
+        assert!(!span.overlaps(&cidr));
+        assert_eq!(AddressRange::parse("10.0.0.7").unwrap().to_string(), "10.0.0.7");
+        assert!(AddressRange::parse("10.0.0.9-10.0.0.1").is_err());
+        assert!(AddressRange::parse("10.0.0.0/33").is_err());
+    }
+
+    #[test]
+    fn test_pool_allocation() {
+        let mut pool = Pool::new("iot", "10.0.0.128/30").unwrap();
+        let mut used = vec![Ipv4Addr::new(10, 0, 0, 129)];
+        for last in [128, 130, 131] {
+            let ip = pool.allocate(|ip| used.contains(&ip)).unwrap();
+            assert_eq!(ip, Ipv4Addr::new(10, 0, 0, last));
+            used.push(ip);
+        }
+        assert!(pool.allocate(|ip| used.contains(&ip)).is_err());
+
+        // A revoked address is handed out again once the cursor wraps around
+        used.retain(|ip| *ip != Ipv4Addr::new(10, 0, 0, 130));
+        assert_eq!(pool.allocate(|ip| used.contains(&ip)).unwrap(), Ipv4Addr::new(10, 0, 0, 130));
+        assert!(Pool::new("bad", "10.0.0.1").is_err());
+    }
+}
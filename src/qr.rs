@@ -0,0 +1,186 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use qrcode::render::svg;
+use qrcode::types::QrError;
+use qrcode::{Color, QrCode};
+use std::fmt;
+use std::path::Path;
+
+/// Modules of blank border around the code, as required by the QR spec
+const QUIET_ZONE: u32 = 4;
+
+/// QR error correction levels, from the smallest code (L) to the most robust (H)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum EcLevel {
+    /// Recovers ~7% of the code
+    L,
+    /// Recovers ~15% of the code
+    #[default]
+    M,
+    /// Recovers ~25% of the code
+    Q,
+    /// Recovers ~30% of the code
+    H,
+}
+
+impl From<EcLevel> for qrcode::EcLevel {
+    fn from(level: EcLevel) -> Self {
+        match level {
+            EcLevel::L => qrcode::EcLevel::L,
+            EcLevel::M => qrcode::EcLevel::M,
+            EcLevel::Q => qrcode::EcLevel::Q,
+            EcLevel::H => qrcode::EcLevel::H,
+        }
+    }
+}
+
+impl fmt::Display for EcLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Default minimum image size in pixels
+pub const DEFAULT_SIZE: u32 = 512;
+/// Largest image size in pixels accepted by `--size`
+pub const MAX_SIZE: u32 = 4096;
+
+/// How QR images are rendered
+#[derive(Debug, Clone, Copy)]
+pub struct QrOptions {
+    /// Minimum width and height of the image in pixels
+    pub size: u32,
+    pub ec_level: EcLevel,
+}
+
+impl Default for QrOptions {
+    fn default() -> Self {
+        Self {
+            size: DEFAULT_SIZE,
+            ec_level: EcLevel::default(),
+        }
+    }
+}
+
+/// Encode a config as a QR code, explaining how to recover if it does not fit
+pub fn encode(config: &str, ec_level: EcLevel) -> Result<QrCode> {
+    match QrCode::with_error_correction_level(config, ec_level.into()) {
+        Ok(code) => Ok(code),
+        Err(QrError::DataTooLong) => {
+            let hint = if ec_level == EcLevel::L {
+                "Shorten the config (fewer AllowedIPs or DNS servers) or import the file instead"
+            } else {
+                "Try a lower error correction level such as --ec-level L, or import the file instead"
+            };
+            anyhow::bail!(
+                "Config is too large for a QR code ({} bytes at error correction level {}). {}",
+                config.len(),
+                ec_level,
+                hint
+            )
+        }
+        Err(e) => anyhow::bail!("Failed to encode QR code: {}", e),
+    }
+}
+
+/// Encode `config` as a PNG QR code
+pub fn png(config: &str, options: QrOptions) -> Result<Vec<u8>> {
+    render_png(&encode(config, options.ec_level)?, options.size)
+}
+
+/// Encode `config` as an SVG QR code
+pub fn svg(config: &str, options: QrOptions) -> Result<String> {
+    Ok(render_svg(&encode(config, options.ec_level)?, options.size))
+}
+
+/// Encode `config` as a QR image for `path`; the format is chosen by its
+/// `.png` or `.svg` extension
+pub fn image(path: &Path, config: &str, options: QrOptions) -> Result<Vec<u8>> {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+    match extension.to_ascii_lowercase().as_str() {
+        "png" => png(config, options),
+        "svg" => Ok(svg(config, options)?.into_bytes()),
+        _ => anyhow::bail!("Unsupported QR image {:?}. Use a .png or .svg file name", path),
+    }
+}
+
+/// Render a black-on-white SVG of at least `size` pixels
+fn render_svg(code: &QrCode, size: u32) -> String {
+    code.render::<svg::Color>()
+        .min_dimensions(size, size)
+        .quiet_zone(true)
+        .build()
+}
+
+/// Render an 8-bit grayscale PNG of at least `size` pixels.
+///
+/// Modules are scaled by a whole number of pixels so the code stays sharp.
+fn render_png(code: &QrCode, size: u32) -> Result<Vec<u8>> {
+    let modules = code.width() as u32;
+    let total = modules + 2 * QUIET_ZONE;
+    let scale = size.div_ceil(total).max(1);
+    let too_large = || anyhow::anyhow!("QR code image of {} pixels is too large", size);
+    let pixels = total.checked_mul(scale).ok_or_else(too_large)?;
+    let len = (pixels as usize).checked_mul(pixels as usize).ok_or_else(too_large)?;
+
+    let colors = code.to_colors();
+    let mut data = Vec::with_capacity(len);
+    for y in 0..pixels {
+        let row = y / scale;
+        for x in 0..pixels {
+            let col = x / scale;
+            let dark = (QUIET_ZONE..QUIET_ZONE + modules).contains(&row)
+                && (QUIET_ZONE..QUIET_ZONE + modules).contains(&col)
+                && colors[((row - QUIET_ZONE) * modules + col - QUIET_ZONE) as usize] == Color::Dark;
+            data.push(if dark { 0x00 } else { 0xff });
+        }
+    }
+
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, pixels, pixels);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().context("Failed to encode PNG")?;
+    writer.write_image_data(&data).context("Failed to encode PNG")?;
+    writer.finish().context("Failed to encode PNG")?;
+    Ok(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "[Interface]\nPrivateKey = abc\nAddress = 10.0.0.2/32\n";
+
+    #[test]
+    fn test_png_is_scaled_to_size() {
+        let code = encode(CONFIG, EcLevel::M).unwrap();
+        let png = render_png(&code, 300).unwrap();
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+
+        let decoder = png::Decoder::new(png.as_slice());
+        let info = decoder.read_info().unwrap().info().clone();
+        let total = code.width() as u32 + 2 * QUIET_ZONE;
+        assert!(info.width >= 300);
+        assert_eq!(info.width % total, 0);
+        assert_eq!(info.width, info.height);
+
+        assert!(render_png(&code, u32::MAX).is_err());
+    }
+
+    #[test]
+    fn test_svg() {
+        let code = encode(CONFIG, EcLevel::H).unwrap();
+        let image = render_svg(&code, 256);
+        assert!(image.starts_with("<?xml"));
+        assert!(image.contains("<svg"));
+    }
+
+    #[test]
+    fn test_too_large_config_is_explained() {
+        let config = "AllowedIPs = 10.0.0.0/24\n".repeat(200);
+        let error = encode(&config, EcLevel::H).err().unwrap().to_string();
+        assert!(error.contains("too large for a QR code"));
+        assert!(error.contains("--ec-level L"));
+    }
+}
@@ -0,0 +1,237 @@
+use crate::config::ClientConfig;
+use crate::export::INTERFACE;
+
+/// Quote a value for `uci batch` (single quotes, with embedded quotes escaped)
+fn uci_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Quote a value for a RouterOS script.
+///
+/// Escapes quotes, backslashes and `$` (variable expansion), and writes
+/// control characters as `\XX` hex escapes so a value stays on one line.
+fn routeros_quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' | '\\' | '$' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            c if c.is_control() && (c as u32) < 0x100 => {
+                quoted.push_str(&format!("\\{:02X}", c as u32))
+            }
+            c if c.is_control() => quoted.push('?'),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Render a client as an OpenWrt `uci batch` script.
+///
+/// Creates the WireGuard interface and its server peer, plus a firewall
+/// zone that masquerades LAN traffic into the tunnel so hosts behind the
+/// router can reach the VPN through the router's single tunnel address.
+/// `uci batch` has no comment syntax, so the script is commands only.
+pub fn uci_batch(client: &ClientConfig) -> String {
+    let iface = INTERFACE;
+    let peer = format!("{}_server", iface);
+    let mut script = format!(
+        "set network.{iface}=interface\n\
+         set network.{iface}.proto='wireguard'\n\
+         set network.{iface}.private_key={private_key}\n\
+         add_list network.{iface}.addresses={address}\n",
+        iface = iface,
+        private_key = uci_quote(&client.keys.private.to_string()),
+        address = uci_quote(&format!("{}/32", client.ip)),
+    );
+    if client.is_full_tunnel() {
+        for dns in client.dns_servers() {
+            script.push_str(&format!("add_list network.{}.dns={}\n", iface, uci_quote(dns)));
+        }
+        for domain in client.dns_search() {
+            script.push_str(&format!("add_list network.{}.dns_search={}\n", iface, uci_quote(domain)));
+        }
+    }
+
+    script.push_str(&format!(
+        "set network.{peer}=wireguard_{iface}\n\
+         set network.{peer}.description={description}\n\
+         set network.{peer}.public_key={public_key}\n\
+         set network.{peer}.endpoint_host={host}\n\
+         set network.{peer}.endpoint_port='{port}'\n\
+         set network.{peer}.persistent_keepalive='{keepalive}'\n\
+         set network.{peer}.route_allowed_ips='1'\n",
+        peer = peer,
+        iface = iface,
+        description = uci_quote("rusty-wire server"),
+        public_key = uci_quote(&client.server_public_key.to_string()),
+        host = uci_quote(&client.server_endpoint),
+        port = client.server_port,
+        keepalive = client.keepalive(),
+    ));
+    for allowed_ip in client.allowed_ip_list() {
+        script.push_str(&format!(
+            "add_list network.{}.allowed_ips={}\n",
+            peer,
+            uci_quote(allowed_ip)
+        ));
+    }
+
+    script.push_str(&format!(
+        "set firewall.{iface}=zone\n\
+         set firewall.{iface}.name='{iface}'\n\
+         set firewall.{iface}.input='REJECT'\n\
+         set firewall.{iface}.output='ACCEPT'\n\
+         set firewall.{iface}.forward='REJECT'\n\
+         set firewall.{iface}.masq='1'\n\
+         set firewall.{iface}.mtu_fix='1'\n\
+         add_list firewall.{iface}.network='{iface}'\n\
+         set firewall.lan_{iface}=forwarding\n\
+         set firewall.lan_{iface}.src='lan'\n\
+         set firewall.lan_{iface}.dest='{iface}'\n\
+         commit network\n\
+         commit firewall\n",
+        iface = iface,
+    ));
+
+    script
+}
+
+/// Render a client as a MikroTik RouterOS script.
+///
+/// Full tunnels are routed with two /1 routes so the router's existing
+/// default route still exists for reaching the endpoint itself.
+pub fn routeros_script(client: &ClientConfig) -> String {
+    let iface = INTERFACE;
+    let comment = routeros_quote(&format!("rusty-wire {}", client.name));
+    let mut script = format!(
+        "# RouterOS config for rusty-wire client {name}\n\
+         # Apply with: /import file-name={file}.rsc\n\
+         /interface wireguard\n\
+         add name={iface} private-key={private_key} comment={comment}\n\
+         /interface wireguard peers\n\
+         add interface={iface} public-key={public_key} endpoint-address={host} \
+         endpoint-port={port} allowed-address={allowed} persistent-keepalive={keepalive}s \
+         comment={comment}\n\
+         /ip address\n\
+         add address={ip}/32 interface={iface} comment={comment}\n\
+         /ip route\n",
+        name = routeros_quote(&client.name),
+        file = client.name.escape_default(),
+        iface = iface,
+        private_key = routeros_quote(&client.keys.private.to_string()),
+        public_key = routeros_quote(&client.server_public_key.to_string()),
+        host = routeros_quote(&client.server_endpoint),
+        port = client.server_port,
+        allowed = client.allowed_ip_list().join(","),
+        keepalive = client.keepalive(),
+        ip = client.ip,
+        comment = comment,
+    );
+
+    if client.is_full_tunnel() {
+        script.push_str(&format!(
+            "# Add a route to {host} via your WAN gateway before enabling these\n\
+             add dst-address=0.0.0.0/1 gateway={iface} comment={comment}\n\
+             add dst-address=128.0.0.0/1 gateway={iface} comment={comment}\n",
+            host = client.server_endpoint.escape_default(),
+            iface = iface,
+            comment = comment,
+        ));
+        // RouterOS has no search domains, only servers
+        let servers = client.dns_servers();
+        if !servers.is_empty() {
+            script.push_str(&format!("/ip dns\nset servers={}\n", servers.join(",")));
+        }
+    } else {
+        for allowed_ip in client.allowed_ip_list() {
+            script.push_str(&format!(
+                "add dst-address={} gateway={} comment={}\n",
+                allowed_ip, iface, comment
+            ));
+        }
+    }
+
+    // Masquerade LAN traffic so the server's /32 AllowedIPs still match
+    script.push_str(&format!(
+        "/ip firewall nat\n\
+         add chain=srcnat out-interface={} action=masquerade comment={}\n",
+        iface, comment
+    ));
+
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_support;
+
+    fn client(allowed_ips: &str) -> ClientConfig {
+        test_support::client(&mut test_support::server(), "branch", allowed_ips)
+    }
+
+    #[test]
+    fn test_uci_batch() {
+        let client = client("10.0.0.0/24");
+        let script = uci_batch(&client);
+
+        assert!(script.contains("set network.wg0.proto='wireguard'\n"));
+        assert!(script.contains("add_list network.wg0.addresses='10.0.0.2/32'\n"));
+        assert!(script.contains("set network.wg0_server=wireguard_wg0\n"));
+        assert!(script.contains(&format!(
+            "set network.wg0_server.public_key='{}'\n",
+            client.server_public_key
+        )));
+        assert!(script.contains("add_list network.wg0_server.allowed_ips='10.0.0.0/24'\n"));
+        assert!(script.contains("set firewall.wg0=zone\n"));
+        assert!(script.ends_with("commit network\ncommit firewall\n"));
+        assert!(!script.contains("network.wg0.dns"));
+    }
+
+    #[test]
+    fn test_uci_quote_escapes_single_quotes() {
+        assert_eq!(uci_quote("it's"), r"'it'\''s'");
+    }
+
+    #[test]
+    fn test_routeros_quote_escapes() {
+        assert_eq!(routeros_quote("a\"b\\c$d"), r#""a\"b\\c\$d""#);
+        assert_eq!(routeros_quote("x\n/system reset"), r#""x\0A/system reset""#);
+    }
+
+    #[test]
+    fn test_routeros_script() {
+        let split = routeros_script(&client("10.0.0.0/24"));
+        assert!(split.contains("/interface wireguard\nadd name=wg0 private-key=\""));
+        assert!(split.starts_with("# RouterOS config for rusty-wire client \"branch\"\n"));
+        assert!(split.contains("endpoint-address=\"vpn.example.com\" endpoint-port=51820 allowed-address=10.0.0.0/24"));
+        assert!(split.contains("add address=10.0.0.2/32 interface=wg0"));
+        assert!(split.contains("add dst-address=10.0.0.0/24 gateway=wg0"));
+
+        let full = routeros_script(&client("0.0.0.0/0"));
+        assert!(full.contains("add dst-address=0.0.0.0/1 gateway=wg0"));
+        assert!(full.contains("set servers=1.1.1.1,9.9.9.9\n"));
+        assert!(!full.contains("dst-address=0.0.0.0/0"));
+    }
+
+    #[test]
+    fn test_search_domains_are_not_dns_servers() {
+        let mut client = client("0.0.0.0/0");
+        client.dns = Some(vec!["10.0.0.1".to_string(), "corp.example".to_string()]);
+
+        let routeros = routeros_script(&client);
+        assert!(routeros.contains("set servers=10.0.0.1\n"));
+        assert!(!routeros.contains("corp.example"));
+
+        let uci = uci_batch(&client);
+        assert!(uci.contains("add_list network.wg0.dns='10.0.0.1'\n"));
+        assert!(uci.contains("add_list network.wg0.dns_search='corp.example'\n"));
+
+        client.dns = Some(vec!["corp.example".to_string()]);
+        assert!(!routeros_script(&client).contains("/ip dns"));
+    }
+}
@@ -0,0 +1,323 @@
+use anyhow::{Context, Result};
+use std::fmt;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+
+use crate::config::ServerConfig;
+
+/// The host's IPv4 routing table
+const IPV4_ROUTES: &str = "/proc/net/route";
+
+/// The host's IPv6 routing table
+const IPV6_ROUTES: &str = "/proc/net/ipv6_route";
+
+/// An IPv4 or IPv6 prefix with its host bits cleared
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Prefix {
+    addr: IpAddr,
+    len: u8,
+}
+
+impl Prefix {
+    pub fn new(addr: IpAddr, len: u8) -> Result<Self> {
+        if len > max_len(addr) {
+            anyhow::bail!("Invalid prefix length {} for {}", len, addr);
+        }
+        Ok(Self {
+            addr: mask(addr, len),
+            len,
+        })
+    }
+
+    /// Parse `10.0.0.0/24`, `fd00::/64` or a bare address as a host prefix
+    pub fn parse(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let (addr, len) = match s.split_once('/') {
+            Some((addr, len)) => (addr, Some(len)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().with_context(|| format!("Invalid IP address in '{}'", s))?;
+        let len = match len {
+            Some(len) => len.parse().with_context(|| format!("Invalid prefix length in '{}'", s))?,
+            None => max_len(addr),
+        };
+        Self::new(addr, len)
+    }
+
+    pub fn len(&self) -> u8 {
+        self.len
+    }
+
+    /// Whether every address of `other` is in this prefix
+    pub fn contains(&self, other: &Prefix) -> bool {
+        self.addr.is_ipv4() == other.addr.is_ipv4()
+            && self.len <= other.len
+            && mask(other.addr, self.len) == self.addr
+    }
+
+    pub fn overlaps(&self, other: &Prefix) -> bool {
+        self.contains(other) || other.contains(self)
+    }
+}
+
+impl fmt::Display for Prefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.len)
+    }
+}
+
+fn max_len(addr: IpAddr) -> u8 {
+    if addr.is_ipv4() { 32 } else { 128 }
+}
+
+fn mask(addr: IpAddr, len: u8) -> IpAddr {
+    match addr {
+        IpAddr::V4(ip) => {
+            let mask = u32::MAX.checked_shl(32 - len as u32).unwrap_or(0);
+            IpAddr::V4(Ipv4Addr::from(u32::from(ip) & mask))
+        }
+        IpAddr::V6(ip) => {
+            let mask = u128::MAX.checked_shl(128 - len as u32).unwrap_or(0);
+            IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask))
+        }
+    }
+}
+
+/// A route in the host's routing table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub prefix: Prefix,
+    pub interface: String,
+}
+
+/// Routes in `/proc/net/route` format; addresses are hex in host byte order
+pub fn parse_ipv4_routes(contents: &str) -> Vec<Route> {
+    contents
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let hex = |i: usize| u32::from_str_radix(fields.get(i)?, 16).ok();
+            let (destination, mask) = (hex(1)?, hex(7)?);
+            Some(Route {
+                prefix: Prefix::new(
+                    IpAddr::V4(Ipv4Addr::from(destination.to_ne_bytes())),
+                    mask.count_ones() as u8,
+                )
+                .ok()?,
+                interface: fields[0].to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Routes in `/proc/net/ipv6_route` format
+pub fn parse_ipv6_routes(contents: &str) -> Vec<Route> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let destination = u128::from_str_radix(fields.first()?, 16).ok()?;
+            let len = u8::from_str_radix(fields.get(1)?, 16).ok()?;
+            Some(Route {
+                prefix: Prefix::new(IpAddr::V6(Ipv6Addr::from(destination)), len).ok()?,
+                interface: fields.get(9)?.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// The host's routes; empty where `/proc/net` does not exist
+pub fn host_routes() -> Result<Vec<Route>> {
+    let read = |path: &str| -> Result<Option<String>> {
+        if !Path::new(path).exists() {
+            return Ok(None);
+        }
+        fs::read_to_string(path)
+            .map(Some)
+            .with_context(|| format!("Failed to read {}", path))
+    };
+    let mut routes = Vec::new();
+    if let Some(contents) = read(IPV4_ROUTES)? {
+        routes.extend(parse_ipv4_routes(&contents));
+    }
+    if let Some(contents) = read(IPV6_ROUTES)? {
+        routes.extend(parse_ipv6_routes(&contents));
+    }
+    Ok(routes)
+}
+
+/// Routes overlapping `network`, other than default routes and those on
+/// loopback or the VPN's own interface
+pub fn conflicts<'a>(network: &Prefix, routes: &'a [Route], wg_interface: &str) -> Vec<&'a Route> {
+    routes
+        .iter()
+        .filter(|route| {
+            route.prefix.len() > 0
+                && route.interface != "lo"
+                && route.interface != wg_interface
+                && route.prefix.overlaps(network)
+        })
+        .collect()
+}
+
+/// Name and AllowedIPs entries of each `[Peer]` in hand-written wg-quick text,
+/// named by a `# Name:` comment before it or by position
+fn hand_written_peers(text: &str) -> Vec<(String, Vec<String>)> {
+    let mut peers: Vec<(String, Vec<String>)> = Vec::new();
+    let mut name = None;
+    let mut in_peer = false;
+    for line in text.lines().map(str::trim) {
+        if let Some(comment) = line.strip_prefix('#') {
+            if let Some(value) = comment.trim().strip_prefix("Name:") {
+                name = Some(value.trim().to_string());
+            }
+        } else if line.starts_with('[') {
+            in_peer = line.eq_ignore_ascii_case("[Peer]");
+            if in_peer {
+                let label = name.take().unwrap_or_else(|| format!("hand-written peer {}", peers.len() + 1));
+                peers.push((label, Vec::new()));
+            }
+        } else if let Some((key, value)) = line.split_once('=')
+            && in_peer
+            && key.trim().eq_ignore_ascii_case("AllowedIPs")
+            && let Some((_, entries)) = peers.last_mut()
+        {
+            entries.extend(value.split(',').map(str::trim).filter(|s| !s.is_empty()).map(str::to_string));
+        }
+    }
+    peers
+}
+
+/// Overlapping and shadowed AllowedIPs in the server state.
+///
+/// On the server each peer's AllowedIPs is its address, plus whatever the
+/// `hand_written` peers of the server config route, such as sites behind
+/// them; those must not overlap between peers. On each client AllowedIPs is
+/// the list of prefixes routed into the tunnel.
+pub fn lint(server: &ServerConfig, hand_written: &str) -> Result<Vec<String>> {
+    let network = Prefix::parse(&server.network)?;
+    let server_ip = IpAddr::V4(server.server_ip()?);
+    let mut findings = Vec::new();
+
+    let mut peers: Vec<(String, Vec<Prefix>)> = Vec::new();
+    for client in &server.clients {
+        let address = Prefix::new(client.ip, max_len(client.ip))?;
+        if client.ip == server_ip {
+            findings.push(format!("peer '{}': {} is the server's address", client.name, address));
+        }
+        peers.push((client.name.clone(), vec![address]));
+    }
+    for (name, entries) in hand_written_peers(hand_written) {
+        let mut prefixes = Vec::new();
+        for entry in entries {
+            match Prefix::parse(&entry) {
+                Ok(prefix) => prefixes.push(prefix),
+                Err(_) => findings.push(format!("peer '{}': invalid AllowedIPs entry '{}'", name, entry)),
+            }
+        }
+        peers.push((name, prefixes));
+    }
+    for (i, (name, prefixes)) in peers.iter().enumerate() {
+        for (other, theirs) in &peers[..i] {
+            for (theirs, prefix) in theirs.iter().flat_map(|t| prefixes.iter().map(move |p| (t, p))) {
+                if theirs == prefix {
+                    findings.push(format!("peers '{}' and '{}' both have AllowedIPs {}", other, name, prefix));
+                } else if theirs.overlaps(prefix) {
+                    findings.push(format!(
+                        "peers '{}' and '{}' have overlapping AllowedIPs {} and {}",
+                        other, name, theirs, prefix
+                    ));
+                }
+            }
+        }
+    }
+
+    for client in &server.clients {
+        let mut prefixes = Vec::new();
+        for entry in client.allowed_ip_list() {
+            match Prefix::parse(entry) {
+                Ok(prefix) => prefixes.push(prefix),
+                Err(_) => findings.push(format!("'{}': invalid AllowedIPs entry '{}'", client.name, entry)),
+            }
+        }
+        for (i, prefix) in prefixes.iter().enumerate() {
+            for earlier in &prefixes[..i] {
+                if earlier == prefix {
+                    findings.push(format!("'{}': {} is listed twice", client.name, prefix));
+                } else if earlier.contains(prefix) {
+                    findings.push(format!("'{}': {} is shadowed by {}", client.name, prefix, earlier));
+                } else if prefix.contains(earlier) {
+                    findings.push(format!("'{}': {} is shadowed by {}", client.name, earlier, prefix));
+                }
+            }
+            if prefix.overlaps(&network) && !prefix.contains(&network) {
+                findings.push(format!(
+                    "'{}': {} routes only part of the VPN network {}",
+                    client.name, prefix, network
+                ));
+            }
+        }
+    }
+
+    Ok(findings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_support;
+
+    #[test]
+    fn test_host_routes() {
+        let v4 = "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT\n\
+                  eth0\t00000000\t0101A8C0\t0003\t0\t0\t100\t00000000\t0\t0\t0\n\
+                  eth0\t0001A8C0\t00000000\t0001\t0\t0\t100\t00FFFFFF\t0\t0\t0\n\
+                  wg0\t0000000A\t00000000\t0001\t0\t0\t0\t00FFFFFF\t0\t0\t0\n";
+        let v6 = "fd000000000000000000000000000000 40 00000000000000000000000000000000 00 \
+                  00000000000000000000000000000000 00000100 00000001 00000000 00000001     eth0\n";
+        let mut routes = parse_ipv4_routes(v4);
+        routes.extend(parse_ipv6_routes(v6));
+        assert_eq!(routes.len(), 4);
+        assert_eq!(routes[1].prefix.to_string(), "192.168.1.0/24");
+        assert_eq!(routes[3].prefix.to_string(), "fd00::/64");
+
+        let lan = Prefix::parse("192.168.1.0/24").unwrap();
+        let found = conflicts(&lan, &routes, "wg0");
+        assert_eq!(found, [&routes[1]]);
+        assert!(conflicts(&Prefix::parse("10.0.0.0/24").unwrap(), &routes, "wg0").is_empty());
+        assert!(!Prefix::parse("fd00::/8").unwrap().overlaps(&Prefix::parse("0.0.0.0/0").unwrap()));
+    }
+
+    #[test]
+    fn test_lint() {
+        let mut server = test_support::server();
+        let routes = [
+            ("laptop", "10.0.0.0/24, 192.168.0.0/16"),
+            ("phone", "0.0.0.0/0, 10.0.0.0/24"),
+            ("site", "10.0.0.0/25, 192.168.1.0/24, nonsense"),
+        ];
+        for (name, allowed_ips) in routes {
+            test_support::client(&mut server, name, allowed_ips);
+        }
+        server.clients[2].ip = server.clients[0].ip;
+        let hand_written = "PostUp = echo custom\n\n\
+                            # Name: office\n[Peer]\nPublicKey = x\nAllowedIPs = 10.0.0.9/32, 192.168.0.0/16\n\n\
+                            [Peer]\nAllowedIPs = 192.168.1.0/24,bad\n";
+
+        assert_eq!(
+            lint(&server, hand_written).unwrap(),
+            [
+                "peer 'hand-written peer 2': invalid AllowedIPs entry 'bad'",
+                "peers 'laptop' and 'site' both have AllowedIPs 10.0.0.2/32",
+                "peers 'office' and 'hand-written peer 2' have overlapping AllowedIPs 192.168.0.0/16 and 192.168.1.0/24",
+                "'phone': 10.0.0.0/24 is shadowed by 0.0.0.0/0",
+                "'site': invalid AllowedIPs entry 'nonsense'",
+                "'site': 10.0.0.0/25 routes only part of the VPN network 10.0.0.0/24",
+            ]
+        );
+        // Clients routing the same site is fine: only the server's side must not overlap
+        assert!(lint(&server, "").unwrap().iter().all(|finding| !finding.contains("192.168")));
+    }
+}
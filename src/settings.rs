@@ -0,0 +1,213 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+use crate::batch::TunnelMode;
+use crate::config::{self, Firewall};
+use crate::export::Format;
+
+/// Project configuration file looked up in the output directory
+pub const FILE_NAME: &str = "rusty-wire.toml";
+
+/// Prefix of the environment variables supplying defaults
+const ENV_PREFIX: &str = "RUSTY_WIRE_";
+
+/// Defaults for command-line options.
+///
+/// Read from `rusty-wire.toml` and `RUSTY_WIRE_*` environment variables;
+/// flags given on the command line always win.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    /// Output directory for configurations
+    pub output: Option<PathBuf>,
+    /// Server context to work in
+    pub server: Option<String>,
+    /// DNS servers pushed to new clients
+    pub dns: Option<Vec<String>>,
+    /// PersistentKeepalive of new clients, in seconds
+    pub keepalive: Option<u16>,
+    /// Tunnel mode of new clients
+    pub tunnel: Option<TunnelMode>,
+    /// Firewall used for NAT rules by `init`
+    pub firewall: Option<Firewall>,
+    /// Format of new server and client configs
+    pub format: Option<Format>,
+}
+
+impl Settings {
+    /// Settings from files and the environment.
+    ///
+    /// `config` replaces the usual files: the user's
+    /// `$XDG_CONFIG_HOME/rusty-wire/config.toml` and `rusty-wire.toml` in the
+    /// output directory, which takes precedence. The environment overrides
+    /// both.
+    pub fn load(config: Option<&Path>, output: Option<&Path>) -> Result<Self> {
+        let env = Self::from_env(|name| std::env::var(name).ok())?;
+        let file = match config {
+            Some(path) => Self::read(path)?,
+            None => {
+                let user = match user_config_path() {
+                    Some(path) if path.exists() => Self::read(&path)?,
+                    _ => Self::default(),
+                };
+                let dir = output
+                    .or(env.output.as_deref())
+                    .or(user.output.as_deref())
+                    .unwrap_or(Path::new("."));
+                let project = dir.join(FILE_NAME);
+                if project.exists() {
+                    user.merge(Self::read(&project)?)
+                } else {
+                    user
+                }
+            }
+        };
+        let settings = file.merge(env);
+        for entry in settings.dns.iter().flatten() {
+            config::validate_dns(entry).context("Invalid dns setting")?;
+        }
+        Ok(settings)
+    }
+
+    /// The format of new servers: `format`, unless it is only for clients
+    pub fn server_format(&self) -> Option<Format> {
+        self.format.filter(Format::is_server_format)
+    }
+
+    /// Read settings from a TOML file
+    pub fn read(path: &Path) -> Result<Self> {
+        let contents =
+            std::fs::read_to_string(path).with_context(|| format!("Failed to read {:?}", path))?;
+        toml::from_str(&contents).with_context(|| format!("Invalid settings in {:?}", path))
+    }
+
+    /// Settings from `RUSTY_WIRE_*` variables, looked up with `var`.
+    ///
+    /// `RUSTY_WIRE_DNS` is a comma-separated list; empty variables are ignored.
+    fn from_env(var: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let get = |name: &str| {
+            var(&format!("{}{}", ENV_PREFIX, name))
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        };
+        Ok(Self {
+            output: get("OUTPUT").map(PathBuf::from),
+            server: get("SERVER"),
+            dns: get("DNS").map(|dns| {
+                dns.split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            }),
+            keepalive: get("KEEPALIVE")
+                .map(|value| {
+                    value
+                        .parse()
+                        .with_context(|| format!("Invalid {}KEEPALIVE '{}'", ENV_PREFIX, value))
+                })
+                .transpose()?,
+            tunnel: get("TUNNEL").map(|value| parse_enum("TUNNEL", &value)).transpose()?,
+            firewall: get("FIREWALL").map(|value| parse_enum("FIREWALL", &value)).transpose()?,
+            format: get("FORMAT").map(|value| parse_enum("FORMAT", &value)).transpose()?,
+        })
+    }
+
+    /// These settings with any set in `other` taking precedence
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            output: other.output.or(self.output),
+            server: other.server.or(self.server),
+            dns: other.dns.or(self.dns),
+            keepalive: other.keepalive.or(self.keepalive),
+            tunnel: other.tunnel.or(self.tunnel),
+            firewall: other.firewall.or(self.firewall),
+            format: other.format.or(self.format),
+        }
+    }
+}
+
+/// `rusty-wire/config.toml` under `$XDG_CONFIG_HOME`, or `~/.config`
+fn user_config_path() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("rusty-wire").join("config.toml"))
+}
+
+fn parse_enum<T: ValueEnum>(name: &str, value: &str) -> Result<T> {
+    T::from_str(value, true).map_err(|e| anyhow::anyhow!("Invalid {}{}: {}", ENV_PREFIX, name, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn env(vars: &[(&str, &str)]) -> Result<Settings> {
+        let vars: HashMap<String, String> =
+            vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        Settings::from_env(|name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn test_parse_file() {
+        let settings: Settings = toml::from_str(
+            "output = \"/etc/wireguard\"\n\
+             dns = [\"10.0.0.1\"]\n\
+             keepalive = 15\n\
+             tunnel = \"full\"\n\
+             firewall = \"nftables\"\n\
+             format = \"networkd\"\n",
+        )
+        .unwrap();
+        assert_eq!(settings.output, Some(PathBuf::from("/etc/wireguard")));
+        assert_eq!(settings.dns, Some(vec!["10.0.0.1".to_string()]));
+        assert_eq!(settings.keepalive, Some(15));
+        assert_eq!(settings.tunnel, Some(TunnelMode::Full));
+        assert_eq!(settings.firewall, Some(Firewall::Nftables));
+        assert_eq!(settings.format, Some(Format::Networkd));
+        assert!(toml::from_str::<Settings>("colour = \"red\"\n").is_err());
+    }
+
+    #[test]
+    fn test_env_overrides_file() {
+        let file = Settings {
+            keepalive: Some(15),
+            format: Some(Format::Networkd),
+            ..Default::default()
+        };
+        let env = env(&[
+            ("RUSTY_WIRE_DNS", "1.1.1.1, 10.0.0.1"),
+            ("RUSTY_WIRE_FORMAT", "conf"),
+            ("RUSTY_WIRE_SERVER", "work"),
+            ("RUSTY_WIRE_TUNNEL", ""),
+        ])
+        .unwrap();
+        let settings = file.merge(env);
+        assert_eq!(settings.dns, Some(vec!["1.1.1.1".to_string(), "10.0.0.1".to_string()]));
+        assert_eq!(settings.keepalive, Some(15));
+        assert_eq!(settings.format, Some(Format::WgQuick));
+        assert_eq!(settings.tunnel, None);
+        assert_eq!(settings.server.as_deref(), Some("work"));
+    }
+
+    #[test]
+    fn test_client_only_format_is_not_a_server_default() {
+        let settings = env(&[("RUSTY_WIRE_FORMAT", "nm")]).unwrap();
+        assert_eq!(settings.format, Some(Format::Nm));
+        assert_eq!(settings.server_format(), None);
+
+        let settings = env(&[("RUSTY_WIRE_FORMAT", "networkd")]).unwrap();
+        assert_eq!(settings.server_format(), Some(Format::Networkd));
+    }
+
+    #[test]
+    fn test_invalid_env() {
+        assert!(env(&[("RUSTY_WIRE_KEEPALIVE", "soon")]).is_err());
+        assert!(env(&[("RUSTY_WIRE_FIREWALL", "pf")]).is_err());
+    }
+}
@@ -0,0 +1,313 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::path::Path;
+
+use crate::batch::TunnelMode;
+use crate::config::{self, ClientConfig, ServerConfig};
+use crate::crypto;
+use crate::routes::Prefix;
+
+/// The whole VPN as it should be, read from YAML (or JSON).
+///
+/// `clients` is required, so a file that forgets it cannot revoke everyone.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DesiredState {
+    #[serde(default)]
+    pub server: DesiredServer,
+    pub clients: Vec<DesiredClient>,
+}
+
+/// Server settings to enforce; omitted fields are left as they are
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DesiredServer {
+    pub endpoint: Option<String>,
+    pub port: Option<u16>,
+    pub network: Option<String>,
+    pub interface: Option<String>,
+}
+
+/// A client that should exist
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DesiredClient {
+    pub name: String,
+    #[serde(default)]
+    pub owner: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Fixed IP; without one, existing clients keep theirs and new ones get the next free IP
+    #[serde(default)]
+    pub ip: Option<IpAddr>,
+    /// Networks routed through the tunnel, instead of `tunnel`
+    #[serde(default)]
+    pub routes: Option<Vec<String>>,
+    #[serde(default)]
+    pub tunnel: Option<TunnelMode>,
+    /// Address pool a new client's IP is taken from
+    #[serde(default)]
+    pub pool: Option<String>,
+}
+
+impl DesiredClient {
+    /// Check the routes are CIDRs and a fixed IP fits `server`
+    fn validate(&self, server: &ServerConfig) -> Result<()> {
+        for route in self.routes.iter().flatten() {
+            if !route.contains('/') {
+                anyhow::bail!("Route '{}' for '{}' is not a CIDR such as 10.1.0.0/24", route, self.name);
+            }
+            Prefix::parse(route).with_context(|| format!("Invalid route for '{}'", self.name))?;
+        }
+        if let Some(ip) = self.ip {
+            server.check_client_ip(ip).with_context(|| format!("Invalid IP for '{}'", self.name))?;
+        }
+        Ok(())
+    }
+
+    /// The client's AllowedIPs on a server with `network`
+    fn allowed_ips(&self, network: &str) -> Result<String> {
+        Ok(match (&self.routes, self.tunnel) {
+            (Some(_), Some(_)) => {
+                anyhow::bail!("Client '{}' sets both routes and tunnel", self.name)
+            }
+            (Some(routes), None) => routes.join(", "),
+            (None, Some(TunnelMode::Full)) => "0.0.0.0/0".to_string(),
+            (None, _) => network.to_string(),
+        })
+    }
+}
+
+/// Read a desired-state file
+pub fn read_desired(path: &Path) -> Result<DesiredState> {
+    let contents =
+        std::fs::read_to_string(path).with_context(|| format!("Failed to read {:?}", path))?;
+    serde_yaml_ng::from_str(&contents).with_context(|| format!("Invalid desired state in {:?}", path))
+}
+
+/// Record `field: old -> new` and update `current` if it differs from `desired`
+fn update<T: PartialEq + Clone + std::fmt::Debug>(
+    changes: &mut Vec<String>,
+    field: &str,
+    current: &mut T,
+    desired: T,
+) {
+    if *current != desired {
+        changes.push(format!("{} {:?} -> {:?}", field, current, desired));
+        *current = desired;
+    }
+}
+
+/// Bring `server` in line with `desired`, returning the plan of what changed.
+///
+/// The desired state is checked as a whole first. Clients missing from it
+/// are revoked so their IPs can be given to others, then existing clients
+/// are updated (swapping IPs is fine) and new ones created with fresh keys.
+pub fn reconcile(server: &mut ServerConfig, desired: &DesiredState) -> Result<Vec<String>> {
+    let mut plan = Vec::new();
+
+    let mut names = HashSet::new();
+    let mut ips = HashSet::new();
+    for client in &desired.clients {
+        if !names.insert(client.name.as_str()) {
+            anyhow::bail!("Client '{}' is listed more than once", client.name);
+        }
+        client.validate(server)?;
+        client.allowed_ips(&server.network)?;
+    }
+    // The IP each existing client ends up with, fixed or kept
+    for wanted in &desired.clients {
+        let ip = match (wanted.ip, server.clients.iter().find(|c| c.name == wanted.name)) {
+            (Some(ip), _) => ip,
+            (None, Some(current)) => current.ip,
+            (None, None) => continue,
+        };
+        if !ips.insert(ip) {
+            anyhow::bail!("IP {} is wanted for more than one client, including '{}'", ip, wanted.name);
+        }
+    }
+
+    // Server settings
+    if let Some(network) = &desired.server.network
+        && config::normalize_network(network)? != server.network
+    {
+        anyhow::bail!(
+            "Changing the network from {} to {} is not supported by sync",
+            server.network,
+            network
+        );
+    }
+    let mut changes = Vec::new();
+    if let Some(endpoint) = &desired.server.endpoint {
+        update(&mut changes, "endpoint", &mut server.endpoint, endpoint.clone());
+    }
+    if let Some(port) = desired.server.port {
+        update(&mut changes, "port", &mut server.port, port);
+    }
+    if let Some(interface) = &desired.server.interface {
+        config::validate_interface(interface)?;
+        update(&mut changes, "interface", &mut server.interface, interface.clone());
+    }
+    if !changes.is_empty() {
+        plan.push(format!("~ server: {}", changes.join(", ")));
+    }
+
+    // Revoke clients that are no longer wanted
+    let removed: Vec<ClientConfig> = server
+        .clients
+        .iter()
+        .filter(|c| !names.contains(c.name.as_str()))
+        .cloned()
+        .collect();
+    for client in &removed {
+        server.remove_client(&client.name)?;
+        plan.push(format!("- peer '{}' {}", client.name, client.ip));
+    }
+
+    // Update existing clients
+    let network = server.network.clone();
+    let (endpoint, port) = (server.endpoint.clone(), server.port);
+    for wanted in &desired.clients {
+        let Some(index) = server.clients.iter().position(|c| c.name == wanted.name) else {
+            continue;
+        };
+        let client = &mut server.clients[index];
+        let mut changes = Vec::new();
+        update(&mut changes, "owner", &mut client.owner, wanted.owner.clone());
+        update(&mut changes, "tags", &mut client.tags, wanted.tags.clone());
+        update(&mut changes, "allowed_ips", &mut client.allowed_ips, wanted.allowed_ips(&network)?);
+        if let Some(ip) = wanted.ip {
+            update(&mut changes, "ip", &mut client.ip, ip);
+        }
+        client.validate()?;
+        // Follow server endpoint changes silently; they are in the server line
+        client.server_endpoint = endpoint.clone();
+        client.server_port = port;
+        if !changes.is_empty() {
+            plan.push(format!("~ peer '{}': {}", client.name, changes.join(", ")));
+        }
+    }
+
+    // Create new clients, fixed IPs first so automatic ones avoid them
+    let mut new: Vec<&DesiredClient> = desired
+        .clients
+        .iter()
+        .filter(|wanted| !server.clients.iter().any(|c| c.name == wanted.name))
+        .collect();
+    new.sort_by_key(|wanted| wanted.ip.is_none());
+    for wanted in new {
+        let mut client = server.new_client(
+            &wanted.name,
+            wanted.ip,
+            wanted.pool.as_deref(),
+            false,
+            crypto::generate_keypair()?,
+        )?;
+        client.owner = wanted.owner.clone();
+        client.tags = wanted.tags.clone();
+        client.allowed_ips = wanted.allowed_ips(&network)?;
+        server.add_client(&client)?;
+        plan.push(format!("+ peer '{}' {}", client.name, client.ip));
+    }
+
+    // Keep the order of the desired state
+    server.clients.sort_by_key(|c| desired.clients.iter().position(|d| d.name == c.name));
+
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_support;
+
+    fn server() -> ServerConfig {
+        let mut server = test_support::server();
+        for name in ["laptop", "old"] {
+            test_support::client(&mut server, name, "10.0.0.0/24");
+        }
+        server
+    }
+
+    fn desired(yaml: &str) -> DesiredState {
+        serde_yaml_ng::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_reconcile() {
+        let mut server = server();
+        let laptop_key = server.clients[0].keys.public;
+        let plan = reconcile(
+            &mut server,
+            &desired(
+                "server:\n  port: 51821\n\
+                 clients:\n\
+                 \x20 - name: phone\n    tunnel: full\n\
+                 \x20 - name: laptop\n    owner: alice\n    tags: [staff]\n    routes: [10.0.0.0/24, 192.168.1.0/24]\n",
+            ),
+        )
+        .unwrap();
+
+        assert_eq!(
+            plan,
+            [
+                "~ server: port 51820 -> 51821",
+                "- peer 'old' 10.0.0.3",
+                "~ peer 'laptop': owner None -> Some(\"alice\"), tags [] -> [\"staff\"], \
+                 allowed_ips \"10.0.0.0/24\" -> \"10.0.0.0/24, 192.168.1.0/24\"",
+                "+ peer 'phone' 10.0.0.4",
+            ]
+        );
+        let names: Vec<&str> = server.clients.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["phone", "laptop"]);
+        assert_eq!(server.clients[1].keys.public, laptop_key);
+        assert_eq!(server.clients[1].server_port, 51821);
+        assert!(server.clients[0].is_full_tunnel());
+    }
+
+    #[test]
+    fn test_reconcile_swaps_ips() {
+        let mut server = server();
+        let plan = reconcile(
+            &mut server,
+            &desired("clients:\n  - name: laptop\n    ip: 10.0.0.3\n  - name: old\n    ip: 10.0.0.2\n"),
+        )
+        .unwrap();
+        assert_eq!(plan, ["~ peer 'laptop': ip 10.0.0.2 -> 10.0.0.3", "~ peer 'old': ip 10.0.0.3 -> 10.0.0.2"]);
+    }
+
+    #[test]
+    fn test_reconcile_is_idempotent() {
+        let mut server = server();
+        let state = desired("clients:\n  - name: laptop\n  - name: old\n");
+        assert!(reconcile(&mut server, &state).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_reconcile_rejects_conflicts() {
+        assert!(reconcile(&mut server(), &desired("clients:\n  - name: a\n  - name: a\n")).is_err());
+        assert!(reconcile(&mut server(), &desired("server:\n  network: 10.1.0.0/24\nclients: []\n")).is_err());
+        assert!(serde_yaml_ng::from_str::<DesiredState>("server:\n  port: 51821\n").is_err());
+        assert!(reconcile(&mut server(), &desired("server:\n  interface: \"eth0 -j DROP\"\nclients: []\n")).is_err());
+        for client in ["routes: [10.1.0.0]", "routes: [banana/24]", "ip: 10.1.0.5", "ip: 10.0.0.1"] {
+            let yaml = format!("clients:\n  - name: laptop\n    {}\n", client);
+            let mut server = server();
+            assert!(reconcile(&mut server, &desired(&yaml)).is_err(), "{}", client);
+            assert_eq!(server.clients.len(), 2, "{}", client);
+        }
+        assert!(
+            reconcile(&mut server(), &desired("clients:\n  - name: laptop\n    ip: 10.0.0.3\n  - name: old\n")).is_err()
+        );
+        assert!(
+            reconcile(&mut server(), &desired("clients:\n  - name: a\n    tunnel: full\n    routes: []\n"))
+                .is_err()
+        );
+        assert!(serde_yaml_ng::from_str::<DesiredState>("clients:\n  - name: a\n    colour: red\n").is_err());
+        assert!(
+            reconcile(&mut server(), &desired("clients:\n  - name: laptop\n    owner: \"a\\nPostUp = x\"\n"))
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use anyhow::{Context, Result};
+use minijinja::{Environment, UndefinedBehavior};
+use serde::Serialize;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config::{ClientConfig, ServerConfig};
+use crate::export::{ExportOptions, RenderedFile, Renderer};
+
+/// Directory, relative to the output directory, holding user templates
+pub const TEMPLATE_DIR: &str = "templates";
+/// Variable a template sets to choose the name of the file it writes
+const OUTPUT_VAR: &str = "output";
+
+/// A client as seen by templates: the stored fields plus derived values
+#[derive(Serialize)]
+struct ClientContext<'a> {
+    #[serde(flatten)]
+    client: &'a ClientConfig,
+    full_tunnel: bool,
+    dns: Vec<&'a str>,
+    allowed_ip_list: Vec<&'a str>,
+    annotation: String,
+}
+
+impl<'a> ClientContext<'a> {
+    fn new(client: &'a ClientConfig) -> Self {
+        Self {
+            client,
+            full_tunnel: client.is_full_tunnel(),
+            dns: client.dns(),
+            allowed_ip_list: client.allowed_ip_list(),
+            annotation: client.annotation(),
+        }
+    }
+}
+
+/// The server as seen by templates: the stored fields plus derived values
+#[derive(Serialize)]
+struct ServerContext<'a> {
+    #[serde(flatten)]
+    server: &'a ServerConfig,
+    address: String,
+    interface_name: &'a str,
+}
+
+/// Renders configs from user-supplied minijinja templates.
+///
+/// A template named `custom` is read from `custom.server.j2` and
+/// `custom.client.j2` in the template directory, and writes `wg0.custom`
+/// and `<client>.custom` unless it sets `output` to another file name.
+/// Server templates see `server` and `clients`; client templates see `client`.
+#[derive(Debug, Clone)]
+pub struct TemplateRenderer {
+    dir: PathBuf,
+    name: String,
+}
+
+impl TemplateRenderer {
+    pub fn new(dir: impl Into<PathBuf>, name: &str) -> Result<Self> {
+        if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+            anyhow::bail!("Invalid template name '{}'", name);
+        }
+        Ok(Self {
+            dir: dir.into(),
+            name: name.to_string(),
+        })
+    }
+
+    /// Render `<name>.<kind>.j2` with `context` into a file named
+    /// `<stem>.<name>`, or whatever the template sets `output` to
+    fn render(&self, kind: &str, stem: &str, context: impl Serialize) -> Result<RenderedFile> {
+        let path = self.dir.join(format!("{}.{}.j2", self.name, kind));
+        let source = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read template {:?}", path))?;
+
+        let mut env = Environment::new();
+        env.set_keep_trailing_newline(true);
+        env.set_undefined_behavior(UndefinedBehavior::Strict);
+        let captured = env
+            .template_from_str(&source)
+            .and_then(|template| template.render_captured(context))
+            .with_context(|| format!("Failed to render template {:?}", path))?;
+        let contents = captured.output().to_string();
+
+        let name = match captured.state().lookup(OUTPUT_VAR) {
+            Some(output) => {
+                let name = output.as_str().map(str::to_string).unwrap_or_else(|| output.to_string());
+                if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+                    anyhow::bail!("Template {:?} sets an invalid output file name '{}'", path, name);
+                }
+                name
+            }
+            None => format!("{}.{}", stem, self.name),
+        };
+        Ok(RenderedFile { name, contents })
+    }
+}
+
+impl Renderer for TemplateRenderer {
+    fn render_client(
+        &self,
+        client: &ClientConfig,
+        _options: &ExportOptions,
+    ) -> Result<Vec<RenderedFile>> {
+        Ok(vec![self.render(
+            "client",
+            &client.name,
+            minijinja::context! { client => ClientContext::new(client) },
+        )?])
+    }
+
+    fn render_server(
+        &self,
+        server: &ServerConfig,
+        _options: &ExportOptions,
+    ) -> Result<Vec<RenderedFile>> {
+        let context = ServerContext {
+            server,
+            address: server.server_ip_with_cidr()?,
+            interface_name: &server.wg_interface,
+        };
+        let clients: Vec<ClientContext> = server.clients.iter().map(ClientContext::new).collect();
+        Ok(vec![self.render(
+            "server",
+            &server.wg_interface,
+            minijinja::context! { server => context, clients => clients },
+        )?])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_support;
+
+    /// A fresh directory for the test `name` holding the template `files`
+    fn template_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rusty-wire-templates-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for (name, contents) in files {
+            fs::write(dir.join(name), contents).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn test_template_renderer() {
+        let dir = template_dir("renderer", &[
+            (
+                "custom.server.j2",
+                "[Interface]\nAddress = {{ server.address }}\nPostUp = /opt/fw {{ server.interface }}\n\
+                 {% for c in clients %}\n{{ c.annotation }}[Peer]\nPublicKey = {{ c.keys.public }}\n{% endfor %}",
+            ),
+            (
+                "custom.client.j2",
+                "# {{ client.name }} {% if client.full_tunnel %}full{% else %}split{% endif %} dns={{ client.dns | join(',') }}\n",
+            ),
+        ]);
+
+        let mut server = test_support::server();
+        server.interface = "eth1".to_string();
+        let client = test_support::client(&mut server, "laptop", "0.0.0.0/0");
+
+        let renderer = TemplateRenderer::new(&dir, "custom").unwrap();
+        let options = ExportOptions::default();
+        let server_files = renderer.render_server(&server, &options).unwrap();
+        assert_eq!(server_files[0].name, "wg0.custom");
+        assert!(server_files[0].contents.contains("Address = 10.0.0.1/24\nPostUp = /opt/fw eth1\n"));
+        assert!(server_files[0].contents.contains(&format!(
+            "# Name: laptop\n# Created: {}\n[Peer]\nPublicKey = {}\n",
+            client.created.as_deref().unwrap(),
+            client.keys.public
+        )));
+
+        let client_files = renderer.render_client(&client, &options).unwrap();
+        assert_eq!(client_files[0].name, "laptop.custom");
+        assert_eq!(client_files[0].contents, "# laptop full dns=1.1.1.1,9.9.9.9\n");
+
+        assert!(TemplateRenderer::new(&dir, "missing").unwrap().render_client(&client, &options).is_err());
+        assert!(TemplateRenderer::new(&dir, "../etc").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_template_output_name() {
+        let dir = template_dir("output-name", &[
+            ("nft.server.j2", "{% set output = server.interface_name ~ \".nft.conf\" %}table inet wg {}\n"),
+            ("nft.client.j2", "{% set output = \"../\" ~ client.name %}\n"),
+        ]);
+        let mut server = test_support::server();
+        let client = test_support::client(&mut server, "laptop", "10.0.0.0/24");
+        let renderer = TemplateRenderer::new(&dir, "nft").unwrap();
+        let options = ExportOptions::default();
+
+        let files = renderer.render_server(&server, &options).unwrap();
+        assert_eq!(files[0].name, "wg0.nft.conf");
+        assert_eq!(files[0].contents, "table inet wg {}\n");
+        assert!(renderer.render_client(&client, &options).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}